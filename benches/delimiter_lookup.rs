@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use torq_lang::lang::lexer::delimiter_lookup::{is_delimiter_binary_search, is_delimiter_mask};
+use torq_lang::lang::lexer::lex_all;
+
+const SOURCE: &str = r#"
+    actor Factorial() in
+        func fact(x) in
+            func fact_cps(n, k) in
+                if n < 2 then k
+                else fact_cps(n - 1, n * k) end
+            end
+            fact_cps(x, 1)
+        end
+        handle ask x in
+            fact(x)
+        end
+    end"#;
+
+pub fn bench_delimiter_lookup(c: &mut Criterion) {
+    c.bench_function("delimiter_binary_search", |b| {
+        b.iter(|| {
+            black_box(SOURCE)
+                .chars()
+                .filter(|c| is_delimiter_binary_search(*c))
+                .count()
+        })
+    });
+    c.bench_function("delimiter_mask", |b| {
+        b.iter(|| {
+            black_box(SOURCE)
+                .chars()
+                .filter(|c| is_delimiter_mask(*c))
+                .count()
+        })
+    });
+    c.bench_function("delimiter_lex_all", |b| {
+        b.iter(|| lex_all(black_box(SOURCE)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_delimiter_lookup);
+criterion_main!(benches);
@@ -6,6 +6,21 @@
  */
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use torq_lang::lang::lexer::{tokenize_into, LexerIter};
+
+const FACTORIAL: &str = r#"
+    actor Factorial() in
+        func fact(x) in
+            func fact_cps(n, k) in
+                if n < 2 then k
+                else fact_cps(n - 1, n * k) end
+            end
+            fact_cps(x, 1)
+        end
+        handle ask x in
+            fact(x)
+        end
+    end"#;
 
 fn fibonacci(n: u64) -> u64 {
     match n {
@@ -19,5 +34,15 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("fib 20", |b| b.iter(|| fibonacci(black_box(20))));
 }
 
-criterion_group!(benches, criterion_benchmark);
+fn bench_tokenize(c: &mut Criterion) {
+    c.bench_function("tokenize_into_reused", |b| {
+        let mut buf = Vec::with_capacity(64);
+        b.iter(|| tokenize_into(black_box(FACTORIAL), &mut buf).unwrap())
+    });
+    c.bench_function("into_vec_fresh", |b| {
+        b.iter(|| LexerIter::new(black_box(FACTORIAL)).into_vec().unwrap())
+    });
+}
+
+criterion_group!(benches, criterion_benchmark, bench_tokenize);
 criterion_main!(benches);
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+use crate::klvm::value::Value;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Address of an actor. Messages are sent to an actor through its reference, which is only
+/// meaningful to the scheduler that spawned the actor.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ActorRef(u64);
+
+impl ActorRef {
+    pub fn new(id: u64) -> ActorRef {
+        ActorRef(id)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ActorRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<actor {}>", self.0)
+    }
+}
+
+/// FIFO queue of messages waiting for an actor. An actor whose mailbox is empty when it executes
+/// `Receive` is suspended until a message is pushed. A mailbox is owned by one scheduler thread,
+/// so it needs no synchronization.
+#[derive(Clone, Debug)]
+pub struct Mailbox<T = Value> {
+    messages: VecDeque<T>,
+}
+
+impl<T> Mailbox<T> {
+    pub fn new() -> Mailbox<T> {
+        Mailbox {
+            messages: VecDeque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Remove and return the oldest message.
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.messages.pop_front()
+    }
+
+    /// Add `msg` after every message already waiting.
+    pub fn push_back(&mut self, msg: T) {
+        self.messages.push_back(msg);
+    }
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Mailbox<T> {
+        Mailbox::new()
+    }
+}
+
+/// Work that an actor instruction hands to the scheduler. The machine that made the request is
+/// suspended until the scheduler completes it, which for `Ask` and `Receive` can take until
+/// another actor runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActorRequest {
+    /// Send `message` to `actor`, and bind the reply to `reply_target`.
+    Ask {
+        actor: ActorRef,
+        message: Value,
+        reply_target: Box<str>,
+    },
+    /// Bind the next message in the mailbox to `target`.
+    Receive { target: Box<str> },
+    /// Send `value` to the actor that asked for the message most recently received.
+    Reply { value: Value },
+    /// Create an actor from the definition named `actor_def`, and bind its reference to `target`.
+    Spawn {
+        actor_def: Box<str>,
+        args: Vec<Value>,
+        target: Box<str>,
+    },
+    /// Send `message` to `actor` without waiting for a reply.
+    Tell { actor: ActorRef, message: Value },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mailbox() {
+        let mut mailbox: Mailbox = Mailbox::new();
+        assert!(mailbox.is_empty());
+        mailbox.push_back(Value::Int32(1));
+        mailbox.push_back(Value::Int32(2));
+        assert_eq!(2, mailbox.len());
+        assert_eq!(Some(Value::Int32(1)), mailbox.pop_front());
+        mailbox.push_back(Value::Int32(3));
+        assert_eq!(Some(Value::Int32(2)), mailbox.pop_front());
+        assert_eq!(Some(Value::Int32(3)), mailbox.pop_front());
+        assert_eq!(None, mailbox.pop_front());
+        assert!(mailbox.is_empty());
+    }
+
+    #[test]
+    fn test_actor_ref() {
+        let actor_ref = ActorRef::new(3);
+        assert_eq!(3, actor_ref.id());
+        assert_eq!("<actor 3>", actor_ref.to_string());
+        assert_eq!("<actor 3>", Value::ActorRef(actor_ref).to_string());
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+use crate::klvm::error::KlvmError;
+use crate::klvm::machine::NativeFunction;
+use crate::klvm::value::Value;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum BitError {
+    BitIndexOutOfRange { max: u8 },
+}
+
+impl fmt::Display for BitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitError::BitIndexOutOfRange { max } => {
+                write!(f, "Bit index is out of range, must be less than {}", max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BitError {}
+
+/// Single-bit operations on the integer types that back `Scalar::Int32` and `Scalar::Int64`. Bit 0
+/// is the least significant bit.
+pub trait BitOps: Sized {
+    fn bit_set(self, bit: u8) -> Result<Self, BitError>;
+    fn bit_clear(self, bit: u8) -> Result<Self, BitError>;
+    fn bit_flip(self, bit: u8) -> Result<Self, BitError>;
+    fn bit_test(self, bit: u8) -> Result<bool, BitError>;
+}
+
+macro_rules! impl_bit_ops {
+    ($t:ty) => {
+        impl BitOps for $t {
+            fn bit_set(self, bit: u8) -> Result<Self, BitError> {
+                Ok(self | bit_mask::<$t>(bit)?)
+            }
+
+            fn bit_clear(self, bit: u8) -> Result<Self, BitError> {
+                Ok(self & !bit_mask::<$t>(bit)?)
+            }
+
+            fn bit_flip(self, bit: u8) -> Result<Self, BitError> {
+                Ok(self ^ bit_mask::<$t>(bit)?)
+            }
+
+            fn bit_test(self, bit: u8) -> Result<bool, BitError> {
+                Ok(self & bit_mask::<$t>(bit)? != 0)
+            }
+        }
+    };
+}
+
+impl_bit_ops!(i32);
+impl_bit_ops!(i64);
+
+/// The `BitOps` methods as KLVM natives, which every machine defines. Each takes an `Int32` or
+/// `Int64` value and an `Int32` bit index. `$bit_test` returns a `Bool`, and the others return a
+/// value of the same type as their first argument.
+pub const NATIVES: [(&str, NativeFunction); 4] = [
+    ("$bit_clear", bit_clear_native),
+    ("$bit_flip", bit_flip_native),
+    ("$bit_set", bit_set_native),
+    ("$bit_test", bit_test_native),
+];
+
+macro_rules! bit_native {
+    ($name:ident, $method:ident, $int32:path, $int64:path) => {
+        fn $name(args: &[Value]) -> Result<Value, KlvmError> {
+            let [value, bit] = args else {
+                return Err(KlvmError::ArityMismatch {
+                    expected: 2,
+                    found: args.len(),
+                });
+            };
+            let bit = match bit {
+                Value::Int32(bit) => *bit,
+                other => {
+                    return Err(KlvmError::TypeMismatch {
+                        expected: "i32",
+                        found: other.type_name(),
+                    })
+                }
+            };
+            match value {
+                Value::Int32(v) => Ok($int32(v.$method(bit_index(bit, 32)?)?)),
+                Value::Int64(v) => Ok($int64(v.$method(bit_index(bit, 64)?)?)),
+                other => Err(KlvmError::TypeMismatch {
+                    expected: "i32",
+                    found: other.type_name(),
+                }),
+            }
+        }
+    };
+}
+
+bit_native!(bit_clear_native, bit_clear, Value::Int32, Value::Int64);
+bit_native!(bit_flip_native, bit_flip, Value::Int32, Value::Int64);
+bit_native!(bit_set_native, bit_set, Value::Int32, Value::Int64);
+bit_native!(bit_test_native, bit_test, Value::Bool, Value::Bool);
+
+/*
+ * Return `bit` as a bit index, or `BitIndexOutOfRange` for a value of `max` bits if `bit` is
+ * negative or too large for any bit index.
+ */
+fn bit_index(bit: i32, max: u8) -> Result<u8, BitError> {
+    u8::try_from(bit).map_err(|_| BitError::BitIndexOutOfRange { max })
+}
+
+/*
+ * Pre-condition: `T` is a primitive integer type
+ */
+fn bit_mask<T>(bit: u8) -> Result<T, BitError>
+where
+    T: From<u8> + std::ops::Shl<u8, Output = T>,
+{
+    let max = (size_of::<T>() * 8) as u8;
+    if bit >= max {
+        return Err(BitError::BitIndexOutOfRange { max });
+    }
+    Ok(T::from(1) << bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit_clear() {
+        assert_eq!(0b0111, 0b1111i32.bit_clear(3).unwrap());
+        assert_eq!(0, 8i32.bit_clear(3).unwrap());
+        assert_eq!(i32::MAX, (-1i32).bit_clear(31).unwrap());
+        assert_eq!(i64::MAX, (-1i64).bit_clear(63).unwrap());
+    }
+
+    #[test]
+    fn test_bit_flip() {
+        assert_eq!(1, 0i32.bit_flip(0).unwrap());
+        assert_eq!(0, 1i32.bit_flip(0).unwrap());
+        assert_eq!(1i64 << 40, 0i64.bit_flip(40).unwrap());
+    }
+
+    #[test]
+    fn test_bit_natives() {
+        let natives: std::collections::HashMap<_, _> = NATIVES.into_iter().collect();
+        let call = |name: &str, args: &[Value]| natives[name](args);
+        let (int32, int64) = (Value::Int32, Value::Int64);
+        assert_eq!(Ok(int32(8)), call("$bit_set", &[int32(0), int32(3)]));
+        assert_eq!(
+            Ok(int32(0b0111)),
+            call("$bit_clear", &[int32(0b1111), int32(3)])
+        );
+        assert_eq!(Ok(int64(1)), call("$bit_flip", &[int64(0), int32(0)]));
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            call("$bit_test", &[int32(128), int32(7)])
+        );
+        assert_eq!(
+            Err(KlvmError::BitIndexOutOfRange { max: 32 }),
+            call("$bit_test", &[int32(0), int32(32)])
+        );
+        assert_eq!(
+            Err(KlvmError::BitIndexOutOfRange { max: 64 }),
+            call("$bit_set", &[int64(0), int32(-1)])
+        );
+        assert_eq!(
+            Err(KlvmError::ArityMismatch {
+                expected: 2,
+                found: 1
+            }),
+            call("$bit_set", &[int32(0)])
+        );
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "i32",
+                found: "i64"
+            }),
+            call("$bit_set", &[int32(0), int64(3)])
+        );
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "i32",
+                found: "f64"
+            }),
+            call("$bit_set", &[Value::Flt64(0.0), int32(3)])
+        );
+    }
+
+    #[test]
+    fn test_bit_out_of_range() {
+        assert_eq!(
+            Err(BitError::BitIndexOutOfRange { max: 32 }),
+            0i32.bit_test(32)
+        );
+        assert_eq!(
+            Err(BitError::BitIndexOutOfRange { max: 32 }),
+            0i32.bit_set(255)
+        );
+        assert_eq!(
+            Err(BitError::BitIndexOutOfRange { max: 64 }),
+            0i64.bit_flip(64)
+        );
+        assert_eq!(Ok(false), 0i64.bit_test(32));
+    }
+
+    #[test]
+    fn test_bit_set() {
+        assert_eq!(8, 0i32.bit_set(3).unwrap());
+        assert_eq!(8, 8i32.bit_set(3).unwrap());
+        assert_eq!(i32::MIN, 0i32.bit_set(31).unwrap());
+        assert_eq!(i64::MIN, 0i64.bit_set(63).unwrap());
+    }
+
+    #[test]
+    fn test_bit_test() {
+        assert_eq!(Ok(true), 128i32.bit_test(7));
+        assert_eq!(Ok(false), 128i32.bit_test(6));
+        assert_eq!(Ok(true), (-1i64).bit_test(63));
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+use crate::klvm::error::KlvmError;
+use crate::klvm::value::Value;
+use std::collections::HashMap;
+
+/// Named bindings held in a chain of scopes. A lookup walks the chain from the innermost scope
+/// to the outermost, so an inner binding shadows an outer binding of the same name. The
+/// outermost scope is the global scope, which is never removed.
+#[derive(Clone, Debug)]
+pub struct Env {
+    // Innermost scope last
+    scopes: Vec<HashMap<Box<str>, Value>>,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    /// Bind `name` in the innermost scope, replacing any binding of `name` in that scope.
+    pub fn bind(&mut self, name: &str, value: Value) {
+        self.scopes.last_mut().unwrap().insert(name.into(), value);
+    }
+
+    /// Number of scopes, including the global scope.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Like `lookup`, but fails with `UndefinedVariable` if `name` is not bound.
+    pub fn get(&self, name: &str) -> Result<&Value, KlvmError> {
+        self.lookup(name)
+            .ok_or_else(|| KlvmError::UndefinedVariable(name.to_string()))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Value> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Like `lookup`, but returns the value for in-place update.
+    pub fn lookup_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    /// Remove the innermost scope and its bindings. The global scope is never removed, so popping
+    /// it raises `InternalError` and leaves its bindings unchanged.
+    pub fn pop_scope(&mut self) -> Result<(), KlvmError> {
+        if self.scopes.len() == 1 {
+            let message = "Cannot pop the global scope";
+            return Err(KlvmError::InternalError(message.to_string()));
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Conservative estimate of the number of bytes used by the bindings.
+    pub fn size_hint(&self) -> usize {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.iter())
+            .map(|(name, value)| size_of::<(Box<str>, Value)>() + name.len() + value.size_hint())
+            .sum()
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shadowing() {
+        let mut env = Env::new();
+        env.bind("x", Value::Int32(1));
+        env.bind("y", Value::Int32(2));
+        env.push_scope();
+        env.bind("x", Value::Str("inner".into()));
+        assert_eq!(Some(&Value::Str("inner".into())), env.lookup("x"));
+        assert_eq!(Some(&Value::Int32(2)), env.lookup("y"));
+        env.pop_scope().unwrap();
+        assert_eq!(Some(&Value::Int32(1)), env.lookup("x"));
+    }
+
+    #[test]
+    fn test_scope_cleanup() {
+        let mut env = Env::new();
+        env.push_scope();
+        env.push_scope();
+        env.bind("x", Value::Null);
+        assert_eq!(3, env.depth());
+        env.pop_scope().unwrap();
+        assert_eq!(None, env.lookup("x"));
+        assert_eq!(2, env.depth());
+        env.pop_scope().unwrap();
+        env.bind("g", Value::Bool(true));
+        assert_eq!(
+            Err(KlvmError::InternalError(
+                "Cannot pop the global scope".to_string()
+            )),
+            env.pop_scope()
+        );
+        assert_eq!(1, env.depth());
+        assert_eq!(Some(&Value::Bool(true)), env.lookup("g"));
+    }
+
+    #[test]
+    fn test_lookup_mut() {
+        let mut env = Env::new();
+        env.bind("x", Value::Int32(1));
+        env.push_scope();
+        *env.lookup_mut("x").unwrap() = Value::Int32(2);
+        assert_eq!(None, env.lookup_mut("y"));
+        env.pop_scope().unwrap();
+        assert_eq!(Some(&Value::Int32(2)), env.lookup("x"));
+    }
+
+    #[test]
+    fn test_not_found() {
+        let mut env = Env::new();
+        assert_eq!(None, env.lookup("x"));
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("x".to_string())),
+            env.get("x")
+        );
+        env.bind("x", Value::Int32(1));
+        assert_eq!(Ok(&Value::Int32(1)), env.get("x"));
+    }
+}
@@ -0,0 +1,248 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+use crate::klvm::actor::ActorRef;
+use crate::klvm::bits::BitError;
+use crate::klvm::value::{UnzipError, Value};
+use std::fmt;
+
+/// Runtime failure raised while executing KLVM code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KlvmError {
+    /// An actor raised `error` and was terminated.
+    ActorPanic {
+        actor: ActorRef,
+        error: Box<KlvmError>,
+    },
+    /// An integer operation overflowed its type.
+    ArithmeticOverflow,
+    /// A function was called with the wrong number of arguments.
+    ArityMismatch { expected: usize, found: usize },
+    /// A bit native was given a bit index that is not less than the bit width of its value.
+    BitIndexOutOfRange { max: u8 },
+    /// An integer division or remainder had a zero divisor.
+    DivisionByZero,
+    /// An element of a tuple being unzipped was not a two-element tuple. Holds its index.
+    ElementNotAPair { index: usize },
+    /// A closure was called in a machine where the function it was created in is not defined, or
+    /// was replaced. Holds the function name.
+    ForeignClosure(String),
+    /// An index was at or past the end of a tuple or list, or a slice range did not lie within
+    /// one.
+    IndexOutOfBounds { index: usize, len: usize },
+    /// The machine reached a state that valid code cannot produce. Holds a description.
+    InternalError(String),
+    /// Values created by the machine would exceed its memory limit. Sizes are estimated by
+    /// `Value::size_hint`.
+    MemoryExceeded { used: usize, limit: usize },
+    /// An ask was sent to an actor that terminated without replying. Holds the actor.
+    NoReply(ActorRef),
+    /// A call would push more frames than the machine's overflow limit.
+    StackOverflow,
+    /// An operand had a different type than the instruction requires. Holds type names as
+    /// returned by `Value::type_name`.
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A spawn names an actor definition that does not exist.
+    UndefinedActor(String),
+    /// A record has no field with the name.
+    UndefinedField(String),
+    /// A call names a function that does not exist.
+    UndefinedFunction(String),
+    /// A jump names a label that does not exist.
+    UndefinedLabel(String),
+    /// No binding exists for the name.
+    UndefinedVariable(String),
+    /// A thrown value reached the bottom of the stack without a handler. Holds the value.
+    Uncaught(Value),
+    /// An object received a message for which it has no method. Holds the message.
+    UnhandledMessage(Value),
+}
+
+impl fmt::Display for KlvmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KlvmError::ActorPanic { actor, error } => {
+                write!(f, "Actor panic in {}: {}", actor, error)
+            }
+            KlvmError::ArithmeticOverflow => write!(f, "Arithmetic overflow"),
+            KlvmError::ArityMismatch { expected, found } => {
+                write!(f, "Expected {} arguments, got {}", expected, found)
+            }
+            KlvmError::BitIndexOutOfRange { max } => {
+                write!(f, "Bit index is out of range, must be less than {}", max)
+            }
+            KlvmError::DivisionByZero => write!(f, "Division by zero"),
+            KlvmError::ElementNotAPair { index } => {
+                write!(f, "Element {} is not a two-element tuple", index)
+            }
+            KlvmError::ForeignClosure(name) => {
+                write!(f, "Closure of a function not defined here: {}", name)
+            }
+            KlvmError::IndexOutOfBounds { index, len } => {
+                write!(f, "Index {} is out of bounds for length {}", index, len)
+            }
+            KlvmError::InternalError(description) => write!(f, "Internal error: {}", description),
+            KlvmError::MemoryExceeded { used, limit } => {
+                write!(
+                    f,
+                    "Memory exceeded: {} bytes used, limit is {}",
+                    used, limit
+                )
+            }
+            KlvmError::NoReply(actor) => write!(f, "No reply from {}", actor),
+            KlvmError::StackOverflow => write!(f, "Stack overflow"),
+            KlvmError::TypeMismatch { expected, found } => {
+                write!(f, "Expected {}, got {}", expected, found)
+            }
+            KlvmError::UndefinedActor(name) => write!(f, "Undefined actor: {}", name),
+            KlvmError::UndefinedField(name) => write!(f, "Undefined field: {}", name),
+            KlvmError::UndefinedFunction(name) => write!(f, "Undefined function: {}", name),
+            KlvmError::UndefinedLabel(name) => write!(f, "Undefined label: {}", name),
+            KlvmError::UndefinedVariable(name) => write!(f, "Undefined variable: {}", name),
+            KlvmError::Uncaught(value) => write!(f, "Uncaught exception: {}", value),
+            KlvmError::UnhandledMessage(message) => write!(f, "Unhandled message: {}", message),
+        }
+    }
+}
+
+impl From<BitError> for KlvmError {
+    fn from(error: BitError) -> KlvmError {
+        match error {
+            BitError::BitIndexOutOfRange { max } => KlvmError::BitIndexOutOfRange { max },
+        }
+    }
+}
+
+impl From<UnzipError> for KlvmError {
+    fn from(error: UnzipError) -> KlvmError {
+        match error {
+            UnzipError::ElementNotAPair { index } => KlvmError::ElementNotAPair { index },
+        }
+    }
+}
+
+impl std::error::Error for KlvmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KlvmError::ActorPanic { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            (
+                KlvmError::ActorPanic {
+                    actor: ActorRef::new(3),
+                    error: Box::new(KlvmError::DivisionByZero),
+                },
+                "Actor panic in <actor 3>: Division by zero",
+            ),
+            (KlvmError::ArithmeticOverflow, "Arithmetic overflow"),
+            (
+                KlvmError::ArityMismatch {
+                    expected: 2,
+                    found: 1,
+                },
+                "Expected 2 arguments, got 1",
+            ),
+            (
+                KlvmError::BitIndexOutOfRange { max: 32 },
+                "Bit index is out of range, must be less than 32",
+            ),
+            (KlvmError::DivisionByZero, "Division by zero"),
+            (
+                KlvmError::ElementNotAPair { index: 2 },
+                "Element 2 is not a two-element tuple",
+            ),
+            (
+                KlvmError::ForeignClosure("f".to_string()),
+                "Closure of a function not defined here: f",
+            ),
+            (
+                KlvmError::IndexOutOfBounds { index: 3, len: 2 },
+                "Index 3 is out of bounds for length 2",
+            ),
+            (
+                KlvmError::InternalError("no frame".to_string()),
+                "Internal error: no frame",
+            ),
+            (
+                KlvmError::MemoryExceeded {
+                    used: 300,
+                    limit: 256,
+                },
+                "Memory exceeded: 300 bytes used, limit is 256",
+            ),
+            (
+                KlvmError::NoReply(ActorRef::new(3)),
+                "No reply from <actor 3>",
+            ),
+            (KlvmError::StackOverflow, "Stack overflow"),
+            (
+                KlvmError::TypeMismatch {
+                    expected: "i32",
+                    found: "str",
+                },
+                "Expected i32, got str",
+            ),
+            (
+                KlvmError::UndefinedActor("Counter".to_string()),
+                "Undefined actor: Counter",
+            ),
+            (
+                KlvmError::UndefinedField("a".to_string()),
+                "Undefined field: a",
+            ),
+            (
+                KlvmError::UndefinedFunction("f".to_string()),
+                "Undefined function: f",
+            ),
+            (
+                KlvmError::UndefinedLabel("top".to_string()),
+                "Undefined label: top",
+            ),
+            (
+                KlvmError::UndefinedVariable("x".to_string()),
+                "Undefined variable: x",
+            ),
+            (
+                KlvmError::Uncaught(Value::Int32(7)),
+                "Uncaught exception: 7",
+            ),
+            (
+                KlvmError::UnhandledMessage(Value::Str("ping".into())),
+                r#"Unhandled message: "ping""#,
+            ),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(expected, error.to_string());
+        }
+    }
+
+    #[test]
+    fn test_error_trait() {
+        let error: Box<dyn std::error::Error> = Box::new(KlvmError::DivisionByZero);
+        assert_eq!("Division by zero", error.to_string());
+        assert!(error.source().is_none());
+        let error = KlvmError::ActorPanic {
+            actor: ActorRef::new(3),
+            error: Box::new(KlvmError::DivisionByZero),
+        };
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!("Division by zero", source.to_string());
+    }
+}
@@ -5,16 +5,510 @@
  * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
  */
 
-trait Instr {
-    fn compute();
+use crate::klvm::error::KlvmError;
+use crate::klvm::value::Scalar;
+use std::collections::HashMap;
+
+/// KLVM instruction. Operands name bindings in the machine's environment. The machine executes an
+/// instruction by matching on its variant, see `Machine::execute_instr`. Jump offsets are relative
+/// to the jump itself, so an offset of 1 continues with the next instruction and a negative offset
+/// loops backward.
+///
+/// Integer instructions raise `ArithmeticOverflow` rather than wrapping, and division and
+/// remainder raise `DivisionByZero` for a zero divisor. Decimal instructions are exact and follow
+/// the same rules, except that a result with too many fractional digits is rounded half up.
+/// Float instructions follow IEEE 754, so division by zero produces an infinity and an invalid
+/// operation produces NaN.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr<'a> {
+    /// `target = abs(operand)`, where `operand` names a `Flt64` binding.
+    AbsFlt64 { operand: &'a str, target: &'a str },
+    /// `reply_target = ask actor_ref message`. Sends `message` to the actor named by `actor_ref`
+    /// and suspends this actor until the reply arrives.
+    AskActor {
+        actor_ref: &'a str,
+        message: &'a str,
+        reply_target: &'a str,
+    },
+    /// `target = left + right`, where both operands name `Dec` bindings.
+    AddDec {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left + right`, where both operands name `Flt32` bindings.
+    AddFlt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left + right`, where both operands name `Flt64` bindings.
+    AddFlt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left + right`, where both operands name `Int32` bindings.
+    AddInt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left + right`, where both operands name `Int64` bindings.
+    AddInt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = func(args...)`. Pushes a frame for `func`, whose return value is bound to
+    /// `target` in this frame. A native `func` runs without a frame and binds `target` at once.
+    Call {
+        func: &'a str,
+        args: Vec<&'a str>,
+        target: &'a str,
+    },
+    /// `target = closure(args...)`. Pushes a frame that runs the closure's code with its captured
+    /// bindings, extended by binding its parameters to `args`.
+    CallClosure {
+        closure: &'a str,
+        args: Vec<&'a str>,
+        target: &'a str,
+    },
+    /// `target = compare(left, right)`, where both operands name `Int32` bindings. The ordering is
+    /// stored as the `Int32` -1, 0, or 1.
+    CmpInt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = a closure` whose code starts at `body_start` in the current function body. The
+    /// bindings named by `capture_names` are copied into the closure when it is created.
+    CreateClosure {
+        params: Vec<Box<str>>,
+        body_start: usize,
+        capture_names: Vec<Box<str>>,
+        target: &'a str,
+    },
+    /// `target = [elements...]`. The length of the list is the number of elements.
+    CreateList {
+        elements: Vec<&'a str>,
+        target: &'a str,
+    },
+    /// `target = {}`, an empty record.
+    CreateRec { target: &'a str },
+    /// `target = (elements...)`. The arity of the tuple is the number of elements.
+    CreateTuple {
+        elements: Vec<&'a str>,
+        target: &'a str,
+    },
+    /// `target = left / right`, where both operands name `Dec` bindings.
+    DivDec {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left / right`, where both operands name `Flt32` bindings.
+    DivFlt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left / right`, where both operands name `Flt64` bindings.
+    DivFlt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left / right`, where both operands name `Int32` bindings.
+    DivInt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left / right`, where both operands name `Int64` bindings.
+    DivInt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = rec.field`, where `rec` names a `Rec` binding.
+    GetField {
+        rec: &'a str,
+        field: Box<str>,
+        target: &'a str,
+    },
+    /// Jump by `offset` unconditionally.
+    Jump { offset: i32 },
+    /// Jump by `offset` when the binding `condition` is truthy: a true `Bool` or a non-zero
+    /// integer.
+    JumpIf { condition: &'a str, offset: i32 },
+    /// Jump to the label `label` when `condition` is truthy. Rewritten to `JumpIf` by
+    /// `resolve_labels`.
+    JumpIfLabel { condition: &'a str, label: &'a str },
+    /// Jump to the label `label`. Rewritten to `Jump` by `resolve_labels`.
+    JumpLabel { label: &'a str },
+    /// Pseudo-instruction marking a jump target. Removed by `resolve_labels`.
+    Label { name: Box<str> },
+    /// `target = value`, where `value` is a compile-time constant.
+    LoadConst { value: Scalar, target: &'a str },
+    /// `target = name`, copying the local binding `name`.
+    LoadLocal { name: &'a str, target: &'a str },
+    /// `target = left * right`, where both operands name `Dec` bindings.
+    MulDec {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left * right`, where both operands name `Flt32` bindings.
+    MulFlt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left * right`, where both operands name `Flt64` bindings.
+    MulFlt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left * right`, where both operands name `Int32` bindings.
+    MulInt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left * right`, where both operands name `Int64` bindings.
+    MulInt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = -operand`, where `operand` names a `Flt32` binding.
+    NegFlt32 { operand: &'a str, target: &'a str },
+    /// `target = -operand`, where `operand` names a `Flt64` binding.
+    NegFlt64 { operand: &'a str, target: &'a str },
+    /// `target = -operand`, where `operand` names an `Int32` binding.
+    NegInt32 { operand: &'a str, target: &'a str },
+    /// `target = -operand`, where `operand` names an `Int64` binding.
+    NegInt64 { operand: &'a str, target: &'a str },
+    /// Remove the handler pushed by the matching `SetupCatch` when its protected region completes
+    /// normally.
+    PopCatch,
+    /// `target = left % right`, where both operands name `Int32` bindings.
+    RemInt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left % right`, where both operands name `Int64` bindings.
+    RemInt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = receive`. Takes the next message from this actor's mailbox, suspending the actor
+    /// while the mailbox is empty.
+    Receive { target: &'a str },
+    /// Send `value` as the reply to the ask that delivered the message most recently received.
+    Reply { value: &'a str },
+    /// Pop this frame and bind `value` to the caller's call target.
+    Return { value: &'a str },
+    /// `rec.field = value`, adding the field to the `Rec` binding `rec` or updating it in place.
+    SetField {
+        rec: &'a str,
+        field: Box<str>,
+        value: &'a str,
+    },
+    /// Push an exception handler for the protected region that follows. The handler's code is at
+    /// `handler_offset` from this instruction and receives the thrown value in `bind_name`.
+    SetupCatch {
+        handler_offset: i32,
+        bind_name: Box<str>,
+    },
+    /// `target = spawn actor_def(args...)`. Creates an actor from the definition named
+    /// `actor_def` and binds its reference to `target`.
+    SpawnActor {
+        actor_def: Box<str>,
+        args: Vec<&'a str>,
+        target: &'a str,
+    },
+    /// `target = sqrt(operand)`, where `operand` names a `Flt64` binding.
+    SqrtFlt64 { operand: &'a str, target: &'a str },
+    /// `name = source`, updating the existing local binding `name`.
+    StoreLocal { source: &'a str, name: &'a str },
+    /// `target = left - right`, where both operands name `Dec` bindings.
+    SubDec {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left - right`, where both operands name `Flt32` bindings.
+    SubFlt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left - right`, where both operands name `Flt64` bindings.
+    SubFlt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left - right`, where both operands name `Int32` bindings.
+    SubInt32 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `target = left - right`, where both operands name `Int64` bindings.
+    SubInt64 {
+        left: &'a str,
+        right: &'a str,
+        target: &'a str,
+    },
+    /// `tell actor_ref message`. Sends `message` to the actor named by `actor_ref` without waiting
+    /// for a reply.
+    TellActor {
+        actor_ref: &'a str,
+        message: &'a str,
+    },
+    /// `return func(args...)`. Replaces this frame with a frame for `func`, which returns to this
+    /// frame's caller, so recursion in tail position runs in constant stack space.
+    TailCall { func: &'a str, args: Vec<&'a str> },
+    /// Unwind to the innermost handler, popping the frames above it, bind `value` to the handler's
+    /// name, and continue at the handler's code.
+    Throw { value: &'a str },
 }
 
-struct AddInt32Ident<'a> {
-    left: i32,
-    right: &'a str,
-    target: &'a str,
+/// Executable instructions. Labels are resolved on construction, so a program never contains
+/// `Label`, `JumpLabel`, or `JumpIfLabel`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program<'a>(Vec<Instr<'a>>);
+
+impl<'a> Program<'a> {
+    pub fn new(mut instrs: Vec<Instr<'a>>) -> Result<Program<'a>, KlvmError> {
+        resolve_labels(&mut instrs)?;
+        Ok(Program(instrs))
+    }
+
+    pub fn get(&self, pc: usize) -> Option<&Instr<'a>> {
+        self.0.get(pc)
+    }
+
+    pub fn instrs(&self) -> &[Instr<'a>] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
 }
 
-impl<'a> AddInt32Ident<'a> {
-    fn compute() {}
+/// Remove `Label` pseudo-instructions and rewrite `JumpLabel` and `JumpIfLabel` into `Jump` and
+/// `JumpIf` with the relative offset of their label. Fails with `UndefinedLabel` if a jump names a
+/// label that does not exist. On failure, `instrs` is left unchanged.
+///
+/// Raw targets, which are the offsets of `Jump`, `JumpIf`, and `SetupCatch` and the `body_start`
+/// of `CreateClosure`, address `instrs` as given. They are rewritten to address the same
+/// instruction once labels are removed. A raw target that addresses a label addresses the
+/// instruction that follows it.
+pub fn resolve_labels(instrs: &mut Vec<Instr>) -> Result<(), KlvmError> {
+    // A label marks the position of the next real instruction. Positions has an extra entry for
+    // the end of the program.
+    let mut labels = HashMap::new();
+    let mut positions = Vec::with_capacity(instrs.len() + 1);
+    let mut position = 0;
+    for instr in instrs.iter() {
+        positions.push(position);
+        match instr {
+            Instr::Label { name } => {
+                labels.insert(name.clone(), position);
+            }
+            _ => position += 1,
+        }
+    }
+    positions.push(position);
+    let offset_to = |label: &str, from: usize| match labels.get(label) {
+        Some(to) => Ok(*to as i32 - from as i32),
+        None => Err(KlvmError::UndefinedLabel(label.to_string())),
+    };
+    // A target outside the program keeps its distance from the start or end
+    let relocate = |target: isize| -> isize {
+        let end = positions.len() as isize - 1;
+        if target < 0 {
+            target
+        } else if target > end {
+            position as isize + target - end
+        } else {
+            positions[target as usize] as isize
+        }
+    };
+    let relocate_offset = |index: usize, offset: i32| {
+        let target = relocate(index as isize + offset as isize);
+        (target - positions[index] as isize) as i32
+    };
+    let mut resolved = Vec::with_capacity(position);
+    for (index, instr) in instrs.iter().enumerate() {
+        let from = resolved.len();
+        let instr = match instr {
+            Instr::CreateClosure {
+                params,
+                body_start,
+                capture_names,
+                target,
+            } => Instr::CreateClosure {
+                params: params.clone(),
+                body_start: relocate(*body_start as isize) as usize,
+                capture_names: capture_names.clone(),
+                target,
+            },
+            Instr::Jump { offset } => Instr::Jump {
+                offset: relocate_offset(index, *offset),
+            },
+            Instr::JumpIf { condition, offset } => Instr::JumpIf {
+                condition,
+                offset: relocate_offset(index, *offset),
+            },
+            Instr::Label { .. } => continue,
+            Instr::JumpIfLabel { condition, label } => Instr::JumpIf {
+                condition,
+                offset: offset_to(label, from)?,
+            },
+            Instr::JumpLabel { label } => Instr::Jump {
+                offset: offset_to(label, from)?,
+            },
+            Instr::SetupCatch {
+                handler_offset,
+                bind_name,
+            } => Instr::SetupCatch {
+                handler_offset: relocate_offset(index, *handler_offset),
+                bind_name: bind_name.clone(),
+            },
+            other => other.clone(),
+        };
+        resolved.push(instr);
+    }
+    *instrs = resolved;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str) -> Instr<'_> {
+        Instr::Label { name: name.into() }
+    }
+
+    #[test]
+    fn test_resolve_labels() {
+        let mut instrs = vec![
+            label("top"),
+            Instr::JumpIfLabel {
+                condition: "done",
+                label: "end",
+            },
+            Instr::LoadLocal {
+                name: "x",
+                target: "y",
+            },
+            Instr::JumpLabel { label: "top" },
+            label("end"),
+            label("also_end"),
+            Instr::JumpLabel { label: "also_end" },
+        ];
+        resolve_labels(&mut instrs).unwrap();
+        let expected = vec![
+            Instr::JumpIf {
+                condition: "done",
+                offset: 3,
+            },
+            Instr::LoadLocal {
+                name: "x",
+                target: "y",
+            },
+            Instr::Jump { offset: -2 },
+            Instr::Jump { offset: 0 },
+        ];
+        assert_eq!(expected, instrs);
+    }
+
+    #[test]
+    fn test_resolve_labels_relocates_raw_targets() {
+        let mut instrs = vec![
+            Instr::SetupCatch {
+                handler_offset: 5,
+                bind_name: "e".into(),
+            },
+            label("a"),
+            Instr::Jump { offset: 2 },
+            label("b"),
+            Instr::JumpIf {
+                condition: "c",
+                offset: -4,
+            },
+            Instr::CreateClosure {
+                params: vec![],
+                body_start: 7,
+                capture_names: vec![],
+                target: "f",
+            },
+            label("c"),
+            Instr::JumpLabel { label: "c" },
+            Instr::Jump { offset: 2 },
+        ];
+        resolve_labels(&mut instrs).unwrap();
+        let expected = vec![
+            // Targets the CreateClosure
+            Instr::SetupCatch {
+                handler_offset: 3,
+                bind_name: "e".into(),
+            },
+            // Targets the JumpIf, after the label "b"
+            Instr::Jump { offset: 1 },
+            // Targets the SetupCatch
+            Instr::JumpIf {
+                condition: "c",
+                offset: -2,
+            },
+            // Starts at the JumpLabel
+            Instr::CreateClosure {
+                params: vec![],
+                body_start: 4,
+                capture_names: vec![],
+                target: "f",
+            },
+            Instr::Jump { offset: 0 },
+            // Targets one past the end
+            Instr::Jump { offset: 2 },
+        ];
+        assert_eq!(expected, instrs);
+    }
+
+    #[test]
+    fn test_program() {
+        let program = Program::new(vec![label("a"), Instr::JumpLabel { label: "a" }]).unwrap();
+        assert_eq!(&[Instr::Jump { offset: 0 }], program.instrs());
+        assert_eq!(1, program.len());
+        assert_eq!(None, program.get(1));
+        assert!(Program::default().is_empty());
+        assert_eq!(
+            Err(KlvmError::UndefinedLabel("b".to_string())),
+            Program::new(vec![Instr::JumpLabel { label: "b" }])
+        );
+    }
+
+    #[test]
+    fn test_resolve_labels_undefined() {
+        let mut instrs = vec![label("a"), Instr::JumpLabel { label: "b" }];
+        assert_eq!(
+            Err(KlvmError::UndefinedLabel("b".to_string())),
+            resolve_labels(&mut instrs)
+        );
+        assert_eq!(2, instrs.len());
+    }
 }
@@ -5,9 +5,1228 @@
  * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
  */
 
-struct Machine {}
+use crate::klvm::actor::{ActorRef, ActorRequest, Mailbox};
+use crate::klvm::bits;
+use crate::klvm::env::Env;
+use crate::klvm::error::KlvmError;
+use crate::klvm::instr::{Instr, Program};
+use crate::klvm::value::{Closure, Decimal, List, Rec, Tuple, Value, TUPLE_NATIVES};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
-impl Machine {}
+/// A named block of instructions. A call binds the parameters to its arguments in a new frame and
+/// executes the body from its first instruction. A function that runs past the end of its body,
+/// including by jumping outside it, returns `Null`.
+///
+/// Each function has an id that is unique in the process. A closure records the id of the
+/// function it was created in, so that calling it where that function is not defined fails
+/// rather than running other code.
+#[derive(Debug)]
+pub struct Function<'a> {
+    id: u64,
+    params: Vec<&'a str>,
+    body: Program<'a>,
+}
+
+static NEXT_FUNCTION_ID: AtomicU64 = AtomicU64::new(0);
+
+impl<'a> Function<'a> {
+    pub fn new(params: Vec<&'a str>, body: Program<'a>) -> Function<'a> {
+        let id = NEXT_FUNCTION_ID.fetch_add(1, Ordering::Relaxed);
+        Function { id, params, body }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// Definition that actors are spawned from. A spawned actor runs `body` in its own machine with
+/// the parameters bound to the spawn arguments, and terminates when the body returns.
+#[derive(Clone, Debug)]
+pub struct ActorDef<'a> {
+    params: Vec<&'a str>,
+    body: Program<'a>,
+}
+
+impl<'a> ActorDef<'a> {
+    pub fn new(params: Vec<&'a str>, body: Program<'a>) -> ActorDef<'a> {
+        ActorDef { params, body }
+    }
+}
+
+/// Native code that implements a function. It receives the call's arguments and returns the value
+/// bound to the call's target. Natives are called with `Call` and do not push a frame.
+pub type NativeFunction = fn(&[Value]) -> Result<Value, KlvmError>;
+
+/// Call-stack entry. A frame executes the body of `func`, starting at `pc`.
+#[derive(Clone, Debug)]
+pub struct Frame<'a> {
+    pub env: Env,
+    /// Index of the next instruction in the function body.
+    pub pc: usize,
+    /// Binding in the caller that receives the return value, or `None` if the frame has no
+    /// caller in the machine.
+    pub return_target: Option<Box<str>>,
+    /// The function being executed, held so that stepping does not look it up by name.
+    pub func: Rc<Function<'a>>,
+    /// Name of the function when the frame was pushed, for stack traces.
+    pub func_name: Box<str>,
+}
+
+impl<'a> Frame<'a> {
+    pub fn new(func_name: &str, func: Rc<Function<'a>>, env: Env) -> Frame<'a> {
+        Frame {
+            env,
+            pc: 0,
+            return_target: None,
+            func,
+            func_name: func_name.into(),
+        }
+    }
+}
+
+/// Exception handler pushed by `SetupCatch`. It belongs to the frame at `frame_index`, and is
+/// discarded when that frame is popped or replaced.
+struct Handler {
+    frame_index: usize,
+    pc: usize,
+    bind_name: Box<str>,
+}
+
+/// Maximum number of bytes, as estimated by `Value::size_hint`, that a machine allocates unless
+/// set otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Maximum number of frames on a machine's call stack unless set otherwise.
+pub const DEFAULT_OVERFLOW_LIMIT: usize = 10_000;
+
+/// Name of the root frame's function, which is the machine's program. It cannot collide with a
+/// Torq identifier.
+const ROOT_FUNC_NAME: &str = "$root";
+
+/// Outcome of `Machine::step`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepResult {
+    Continue,
+    /// The program returned, or ran past its end, with the value.
+    Halted(Value),
+    /// An actor instruction needs the scheduler. The instruction has been executed, and the machine
+    /// continues with the next one once the scheduler has completed the request.
+    Request(ActorRequest),
+}
+
+/// Executes a program against a stack of frames. The root frame runs the program and is never
+/// popped, so `bind`, `lookup`, and `execute_instr` always have a current frame. A `Return` in
+/// the root frame halts the machine. Pushing a frame beyond the overflow limit raises
+/// `StackOverflow`, and creating composite values beyond the memory limit raises `MemoryExceeded`.
+/// Memory is counted as it is allocated and never released. A thrown value without a handler
+/// raises `Uncaught`. Actor instructions are completed by a scheduler, so a machine run outside
+/// one raises `InternalError` on reaching them.
+pub struct Machine<'a> {
+    functions: HashMap<&'a str, Rc<Function<'a>>>,
+    natives: HashMap<&'a str, NativeFunction>,
+    frames: Vec<Frame<'a>>,
+    handlers: Vec<Handler>,
+    memory_limit: usize,
+    memory_used: usize,
+    overflow_limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(program: Program<'a>) -> Machine<'a> {
+        let root = Rc::new(Function::new(vec![], program));
+        let mut functions = HashMap::new();
+        functions.insert(ROOT_FUNC_NAME, Rc::clone(&root));
+        Machine {
+            functions,
+            natives: bits::NATIVES.into_iter().chain(TUPLE_NATIVES).collect(),
+            frames: vec![Frame::new(ROOT_FUNC_NAME, root, Env::new())],
+            handlers: vec![],
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            memory_used: 0,
+            overflow_limit: DEFAULT_OVERFLOW_LIMIT,
+        }
+    }
+
+    /// Add the estimated size of `value` to the memory used. Raises `MemoryExceeded`, and leaves
+    /// the memory used unchanged, if the total would exceed the memory limit.
+    pub fn allocate_check(&mut self, value: &Value) -> Result<(), KlvmError> {
+        let used = self.memory_used.saturating_add(value.size_hint());
+        if used > self.memory_limit {
+            return Err(KlvmError::MemoryExceeded {
+                used,
+                limit: self.memory_limit,
+            });
+        }
+        self.memory_used = used;
+        Ok(())
+    }
+
+    /// Bind `name` in the current frame.
+    pub fn bind(&mut self, name: &str, value: Value) {
+        self.current().env.bind(name, value);
+    }
+
+    /// Call the function `func` and run until it returns. On failure, the frames pushed by the
+    /// call are discarded. Handlers set up before the call cannot catch values thrown within it,
+    /// so a thrown value that is not caught within the call raises `Uncaught`.
+    pub fn call(&mut self, func: &str, args: Vec<Value>) -> Result<Value, KlvmError> {
+        let depth = self.frames.len();
+        let frame = self.new_frame(func, args, None)?;
+        self.push_frame(frame)?;
+        let handlers = std::mem::take(&mut self.handlers);
+        let answer = self.run();
+        self.frames.truncate(depth);
+        self.handlers = handlers;
+        answer
+    }
+
+    pub fn define_function(&mut self, name: &'a str, function: Function<'a>) {
+        self.functions.insert(name, Rc::new(function));
+    }
+
+    /// Define `name` as a native function. A native takes precedence over a function with the same
+    /// name.
+    pub fn define_native(&mut self, name: &'a str, native: NativeFunction) {
+        self.natives.insert(name, native);
+    }
+
+    /// Run the program until it halts.
+    pub fn execute(&mut self) -> Result<Value, KlvmError> {
+        self.run()
+    }
+
+    /// Execute one instruction in the current frame and advance its program counter. Returns
+    /// `Halted` with the result when the instruction returns from a frame that has no caller to
+    /// receive it, and `Request` when the instruction is an actor instruction.
+    pub fn execute_instr(&mut self, instr: &Instr<'a>) -> Result<StepResult, KlvmError> {
+        let mut offset = 1;
+        let mut answer = StepResult::Continue;
+        match instr {
+            Instr::AbsFlt64 { operand, target } => {
+                self.flt64_unary_op(operand, target, f64::abs)?
+            }
+            Instr::AskActor {
+                actor_ref,
+                message,
+                reply_target,
+            } => {
+                answer = StepResult::Request(ActorRequest::Ask {
+                    actor: self.actor_ref(actor_ref)?,
+                    message: self.value(message)?.clone(),
+                    reply_target: (*reply_target).into(),
+                });
+            }
+            Instr::AddDec {
+                left,
+                right,
+                target,
+            } => self.dec_op(left, right, target, Decimal::checked_add)?,
+            Instr::AddFlt32 {
+                left,
+                right,
+                target,
+            } => self.flt32_op(left, right, target, |a, b| a + b)?,
+            Instr::AddFlt64 {
+                left,
+                right,
+                target,
+            } => self.flt64_op(left, right, target, |a, b| a + b)?,
+            Instr::AddInt32 {
+                left,
+                right,
+                target,
+            } => self.int32_op(left, right, target, i32::checked_add)?,
+            Instr::AddInt64 {
+                left,
+                right,
+                target,
+            } => self.int64_op(left, right, target, i64::checked_add)?,
+            Instr::Call { func, args, target } => {
+                let args = self.values(args)?;
+                if let Some(native) = self.natives.get(func).copied() {
+                    let answer = native(&args)?;
+                    self.bind(target, answer);
+                } else {
+                    let frame = self.new_frame(func, args, Some((*target).into()))?;
+                    self.push_frame(frame)?;
+                    let caller = self.frames.len() - 2;
+                    self.frames[caller].pc += 1;
+                    return Ok(StepResult::Continue);
+                }
+            }
+            Instr::CallClosure {
+                closure,
+                args,
+                target,
+            } => {
+                let closure = self.closure(closure)?;
+                let func = Rc::clone(self.function(closure.func_name())?);
+                if func.id != closure.func_id() {
+                    let func_name = closure.func_name().to_string();
+                    return Err(KlvmError::ForeignClosure(func_name));
+                }
+                let args = self.values(args)?;
+                if closure.params().len() != args.len() {
+                    return Err(KlvmError::ArityMismatch {
+                        expected: closure.params().len(),
+                        found: args.len(),
+                    });
+                }
+                let mut env = closure.env().clone();
+                env.push_scope();
+                for (param, arg) in closure.params().iter().zip(args) {
+                    env.bind(param, arg);
+                }
+                let mut frame = Frame::new(closure.func_name(), func, env);
+                frame.pc = closure.start_pc();
+                frame.return_target = Some((*target).into());
+                self.push_frame(frame)?;
+                let caller = self.frames.len() - 2;
+                self.frames[caller].pc += 1;
+                return Ok(StepResult::Continue);
+            }
+            Instr::CmpInt32 {
+                left,
+                right,
+                target,
+            } => {
+                let ordering = self.int32(left)?.cmp(&self.int32(right)?);
+                self.bind(target, Value::Int32(ordering as i32));
+            }
+            Instr::CreateClosure {
+                params,
+                body_start,
+                capture_names,
+                target,
+            } => {
+                let mut env = Env::new();
+                for name in capture_names {
+                    env.bind(name, self.value(name)?.clone());
+                }
+                let frame = self.frames.last().unwrap();
+                let closure = Closure::new(
+                    &frame.func_name,
+                    frame.func.id,
+                    *body_start,
+                    params.clone(),
+                    env,
+                );
+                let closure = Value::Closure(Arc::new(closure));
+                self.allocate_check(&closure)?;
+                self.bind(target, closure);
+            }
+            Instr::CreateList { elements, target } => {
+                let list = Value::List(List::from(self.values(elements)?));
+                self.allocate_check(&list)?;
+                self.bind(target, list);
+            }
+            Instr::CreateRec { target } => {
+                let rec = Value::Rec(Rec::new());
+                self.allocate_check(&rec)?;
+                self.bind(target, rec);
+            }
+            Instr::CreateTuple { elements, target } => {
+                let tuple = Value::Tuple(Tuple::new(self.values(elements)?));
+                self.allocate_check(&tuple)?;
+                self.bind(target, tuple);
+            }
+            Instr::DivDec {
+                left,
+                right,
+                target,
+            } => self.dec_div_op(left, right, target)?,
+            Instr::DivFlt32 {
+                left,
+                right,
+                target,
+            } => self.flt32_op(left, right, target, |a, b| a / b)?,
+            Instr::DivFlt64 {
+                left,
+                right,
+                target,
+            } => self.flt64_op(left, right, target, |a, b| a / b)?,
+            Instr::DivInt32 {
+                left,
+                right,
+                target,
+            } => self.int32_div_op(left, right, target, i32::checked_div)?,
+            Instr::DivInt64 {
+                left,
+                right,
+                target,
+            } => self.int64_div_op(left, right, target, i64::checked_div)?,
+            Instr::GetField { rec, field, target } => {
+                let value = self.rec(rec)?.get(field).cloned();
+                let value = value.ok_or_else(|| KlvmError::UndefinedField(field.to_string()))?;
+                self.bind(target, value);
+            }
+            Instr::Jump { offset: jump } => offset = *jump,
+            Instr::JumpIf {
+                condition,
+                offset: jump,
+            } => {
+                if self.truthy(condition)? {
+                    offset = *jump;
+                }
+            }
+            Instr::JumpIfLabel { label, .. } | Instr::JumpLabel { label } => {
+                return Err(KlvmError::UndefinedLabel(label.to_string()));
+            }
+            Instr::Label { .. } => {}
+            Instr::LoadConst { value, target } => self.bind(target, Value::from(value.clone())),
+            Instr::LoadLocal { name, target } => {
+                let value = self.value(name)?.clone();
+                self.bind(target, value);
+            }
+            Instr::MulDec {
+                left,
+                right,
+                target,
+            } => self.dec_op(left, right, target, Decimal::checked_mul)?,
+            Instr::MulFlt32 {
+                left,
+                right,
+                target,
+            } => self.flt32_op(left, right, target, |a, b| a * b)?,
+            Instr::MulFlt64 {
+                left,
+                right,
+                target,
+            } => self.flt64_op(left, right, target, |a, b| a * b)?,
+            Instr::MulInt32 {
+                left,
+                right,
+                target,
+            } => self.int32_op(left, right, target, i32::checked_mul)?,
+            Instr::MulInt64 {
+                left,
+                right,
+                target,
+            } => self.int64_op(left, right, target, i64::checked_mul)?,
+            Instr::NegFlt32 { operand, target } => self.flt32_unary_op(operand, target, |a| -a)?,
+            Instr::NegFlt64 { operand, target } => self.flt64_unary_op(operand, target, |a| -a)?,
+            Instr::NegInt32 { operand, target } => {
+                let answer = self.int32(operand)?.checked_neg();
+                self.bind_int(target, answer.map(Value::Int32))?;
+            }
+            Instr::NegInt64 { operand, target } => {
+                let answer = self.int64(operand)?.checked_neg();
+                self.bind_int(target, answer.map(Value::Int64))?;
+            }
+            Instr::RemInt32 {
+                left,
+                right,
+                target,
+            } => self.int32_div_op(left, right, target, i32::checked_rem)?,
+            Instr::RemInt64 {
+                left,
+                right,
+                target,
+            } => self.int64_div_op(left, right, target, i64::checked_rem)?,
+            Instr::PopCatch => {
+                let frame_index = self.frames.len() - 1;
+                match self.handlers.last() {
+                    Some(handler) if handler.frame_index == frame_index => self.handlers.pop(),
+                    _ => {
+                        let message = "PopCatch without a handler in the current frame";
+                        return Err(KlvmError::InternalError(message.to_string()));
+                    }
+                };
+            }
+            Instr::Receive { target } => {
+                answer = StepResult::Request(ActorRequest::Receive {
+                    target: (*target).into(),
+                });
+            }
+            Instr::Reply { value } => {
+                answer = StepResult::Request(ActorRequest::Reply {
+                    value: self.value(value)?.clone(),
+                });
+            }
+            Instr::Return { value } => {
+                let value = self.value(value)?.clone();
+                return Ok(self.return_value(value));
+            }
+            Instr::SetField { rec, field, value } => {
+                let value = self.value(value)?.clone();
+                self.allocate_check(&value)?;
+                self.rec_mut(rec)?.set(field, value);
+            }
+            Instr::SetupCatch {
+                handler_offset,
+                bind_name,
+            } => {
+                let frame_index = self.frames.len() - 1;
+                let pc = self.current().pc;
+                self.handlers.push(Handler {
+                    frame_index,
+                    pc: pc.wrapping_add_signed(*handler_offset as isize),
+                    bind_name: bind_name.clone(),
+                });
+            }
+            Instr::SpawnActor {
+                actor_def,
+                args,
+                target,
+            } => {
+                answer = StepResult::Request(ActorRequest::Spawn {
+                    actor_def: actor_def.clone(),
+                    args: self.values(args)?,
+                    target: (*target).into(),
+                });
+            }
+            Instr::SqrtFlt64 { operand, target } => {
+                self.flt64_unary_op(operand, target, f64::sqrt)?
+            }
+            Instr::StoreLocal { source, name } => {
+                self.value(name)?;
+                let value = self.value(source)?.clone();
+                self.bind(name, value);
+            }
+            Instr::SubDec {
+                left,
+                right,
+                target,
+            } => self.dec_op(left, right, target, Decimal::checked_sub)?,
+            Instr::SubFlt32 {
+                left,
+                right,
+                target,
+            } => self.flt32_op(left, right, target, |a, b| a - b)?,
+            Instr::SubFlt64 {
+                left,
+                right,
+                target,
+            } => self.flt64_op(left, right, target, |a, b| a - b)?,
+            Instr::SubInt32 {
+                left,
+                right,
+                target,
+            } => self.int32_op(left, right, target, i32::checked_sub)?,
+            Instr::SubInt64 {
+                left,
+                right,
+                target,
+            } => self.int64_op(left, right, target, i64::checked_sub)?,
+            Instr::TellActor { actor_ref, message } => {
+                answer = StepResult::Request(ActorRequest::Tell {
+                    actor: self.actor_ref(actor_ref)?,
+                    message: self.value(message)?.clone(),
+                });
+            }
+            Instr::TailCall { func, args } => {
+                let args = self.values(args)?;
+                let return_target = self.current().return_target.clone();
+                let frame = self.new_frame(func, args, return_target)?;
+                *self.current() = frame;
+                self.discard_handlers(self.frames.len() - 1);
+                return Ok(StepResult::Continue);
+            }
+            Instr::Throw { value } => {
+                let value = self.value(value)?.clone();
+                let Some(handler) = self.handlers.pop() else {
+                    return Err(KlvmError::Uncaught(value));
+                };
+                self.frames.truncate(handler.frame_index + 1);
+                self.bind(&handler.bind_name, value);
+                self.current().pc = handler.pc;
+                return Ok(StepResult::Continue);
+            }
+        }
+        let current = self.current();
+        current.pc = current.pc.wrapping_add_signed(offset as isize);
+        Ok(answer)
+    }
+
+    /// Look up `name` in the current frame.
+    pub fn lookup(&self, name: &str) -> Option<&Value> {
+        self.frames.last().unwrap().env.lookup(name)
+    }
+
+    /// Execute exactly one instruction, for use by a debugger or a scheduler. Once halted, the
+    /// machine keeps reporting `Halted`.
+    pub fn step(&mut self) -> Result<StepResult, KlvmError> {
+        self.step_frame()
+    }
+
+    /// The call stack, with the current frame last.
+    pub fn frames(&self) -> &[Frame<'a>] {
+        &self.frames
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    /// Estimated number of bytes allocated by the instructions executed so far.
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    pub fn overflow_limit(&self) -> usize {
+        self.overflow_limit
+    }
+
+    pub fn set_memory_limit(&mut self, memory_limit: usize) {
+        self.memory_limit = memory_limit;
+    }
+
+    pub fn set_overflow_limit(&mut self, overflow_limit: usize) {
+        self.overflow_limit = overflow_limit;
+    }
+
+    fn actor_ref(&self, name: &str) -> Result<ActorRef, KlvmError> {
+        match self.value(name)? {
+            Value::ActorRef(actor_ref) => Ok(*actor_ref),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "actor",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /*
+     * Post-condition:
+     *   `target` is bound to `answer`, or `ArithmeticOverflow` is returned if `answer` is `None`.
+     */
+    fn bind_int(&mut self, target: &str, answer: Option<Value>) -> Result<(), KlvmError> {
+        let answer = answer.ok_or(KlvmError::ArithmeticOverflow)?;
+        self.bind(target, answer);
+        Ok(())
+    }
+
+    fn closure(&self, name: &str) -> Result<Arc<Closure>, KlvmError> {
+        match self.value(name)? {
+            Value::Closure(closure) => Ok(Arc::clone(closure)),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "closure",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn current(&mut self) -> &mut Frame<'a> {
+        self.frames.last_mut().unwrap()
+    }
+
+    /*
+     * Post-condition:
+     *   Handlers that belong to frames at or above `frame_count` are removed.
+     */
+    fn discard_handlers(&mut self, frame_count: usize) {
+        while let Some(handler) = self.handlers.last() {
+            if handler.frame_index < frame_count {
+                break;
+            }
+            self.handlers.pop();
+        }
+    }
+
+    fn dec(&self, name: &str) -> Result<Decimal, KlvmError> {
+        match self.value(name)? {
+            Value::Dec(v) => Ok(*v),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "dec",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn dec_div_op(&mut self, left: &str, right: &str, target: &str) -> Result<(), KlvmError> {
+        if self.dec(right)?.mantissa() == 0 {
+            return Err(KlvmError::DivisionByZero);
+        }
+        self.dec_op(left, right, target, Decimal::checked_div)
+    }
+
+    fn dec_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(Decimal, Decimal) -> Option<Decimal>,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.dec(left)?, self.dec(right)?).ok_or(KlvmError::ArithmeticOverflow)?;
+        self.bind(target, Value::Dec(answer));
+        Ok(())
+    }
+
+    fn flt32(&self, name: &str) -> Result<f32, KlvmError> {
+        match self.value(name)? {
+            Value::Flt32(v) => Ok(*v),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "f32",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn flt32_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(f32, f32) -> f32,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.flt32(left)?, self.flt32(right)?);
+        self.bind(target, Value::Flt32(answer));
+        Ok(())
+    }
+
+    fn flt32_unary_op(
+        &mut self,
+        operand: &str,
+        target: &str,
+        op: fn(f32) -> f32,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.flt32(operand)?);
+        self.bind(target, Value::Flt32(answer));
+        Ok(())
+    }
+
+    fn flt64(&self, name: &str) -> Result<f64, KlvmError> {
+        match self.value(name)? {
+            Value::Flt64(v) => Ok(*v),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "f64",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn flt64_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(f64, f64) -> f64,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.flt64(left)?, self.flt64(right)?);
+        self.bind(target, Value::Flt64(answer));
+        Ok(())
+    }
+
+    fn flt64_unary_op(
+        &mut self,
+        operand: &str,
+        target: &str,
+        op: fn(f64) -> f64,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.flt64(operand)?);
+        self.bind(target, Value::Flt64(answer));
+        Ok(())
+    }
+
+    fn function(&self, name: &str) -> Result<&Rc<Function<'a>>, KlvmError> {
+        self.functions
+            .get(name)
+            .ok_or_else(|| KlvmError::UndefinedFunction(name.to_string()))
+    }
+
+    fn int32(&self, name: &str) -> Result<i32, KlvmError> {
+        match self.value(name)? {
+            Value::Int32(v) => Ok(*v),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "i32",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /*
+     * Pre-condition:
+     *   `op` is a checked division or remainder, which returns `None` for a zero divisor and for
+     *   `i32::MIN / -1`.
+     */
+    fn int32_div_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(i32, i32) -> Option<i32>,
+    ) -> Result<(), KlvmError> {
+        if self.int32(right)? == 0 {
+            return Err(KlvmError::DivisionByZero);
+        }
+        self.int32_op(left, right, target, op)
+    }
+
+    fn int32_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(i32, i32) -> Option<i32>,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.int32(left)?, self.int32(right)?);
+        self.bind_int(target, answer.map(Value::Int32))
+    }
+
+    fn int64(&self, name: &str) -> Result<i64, KlvmError> {
+        match self.value(name)? {
+            Value::Int64(v) => Ok(*v),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "i64",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /*
+     * Pre-condition:
+     *   `op` is a checked division or remainder, which returns `None` for a zero divisor and for
+     *   `i64::MIN / -1`.
+     */
+    fn int64_div_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(i64, i64) -> Option<i64>,
+    ) -> Result<(), KlvmError> {
+        if self.int64(right)? == 0 {
+            return Err(KlvmError::DivisionByZero);
+        }
+        self.int64_op(left, right, target, op)
+    }
+
+    fn int64_op(
+        &mut self,
+        left: &str,
+        right: &str,
+        target: &str,
+        op: fn(i64, i64) -> Option<i64>,
+    ) -> Result<(), KlvmError> {
+        let answer = op(self.int64(left)?, self.int64(right)?);
+        self.bind_int(target, answer.map(Value::Int64))
+    }
+
+    fn new_frame(
+        &self,
+        func: &str,
+        args: Vec<Value>,
+        return_target: Option<Box<str>>,
+    ) -> Result<Frame<'a>, KlvmError> {
+        let func_name = func;
+        let func = Rc::clone(self.function(func_name)?);
+        if func.params.len() != args.len() {
+            return Err(KlvmError::ArityMismatch {
+                expected: func.params.len(),
+                found: args.len(),
+            });
+        }
+        let mut env = Env::new();
+        for (param, arg) in func.params.iter().zip(args) {
+            env.bind(param, arg);
+        }
+        let mut frame = Frame::new(func_name, func, env);
+        frame.return_target = return_target;
+        Ok(frame)
+    }
+
+    fn push_frame(&mut self, frame: Frame<'a>) -> Result<(), KlvmError> {
+        if self.frames.len() >= self.overflow_limit {
+            return Err(KlvmError::StackOverflow);
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn rec(&self, name: &str) -> Result<&Rec, KlvmError> {
+        match self.value(name)? {
+            Value::Rec(rec) => Ok(rec),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "rec",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn rec_mut(&mut self, name: &str) -> Result<&mut Rec, KlvmError> {
+        let value = self.current().env.lookup_mut(name);
+        match value.ok_or_else(|| KlvmError::UndefinedVariable(name.to_string()))? {
+            Value::Rec(rec) => Ok(rec),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "rec",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    /*
+     * Post-condition:
+     *   The current frame is popped unless it is the root frame. If the popped frame has a
+     *   return target, `value` is bound to it in the caller and `Continue` is returned.
+     *   Otherwise, `Halted(value)` is returned.
+     */
+    fn return_value(&mut self, value: Value) -> StepResult {
+        if self.frames.len() == 1 {
+            return StepResult::Halted(value);
+        }
+        let frame = self.frames.pop().unwrap();
+        self.discard_handlers(self.frames.len());
+        match frame.return_target {
+            Some(target) => {
+                self.bind(&target, value);
+                StepResult::Continue
+            }
+            None => StepResult::Halted(value),
+        }
+    }
+
+    /*
+     * Pre-condition:
+     *   The current frame is the root frame or was pushed by `call`.
+     *
+     * Post-condition:
+     *   Instructions are executed until the current frame returns. An actor instruction raises
+     *   `InternalError`, since no scheduler can complete it.
+     */
+    fn run(&mut self) -> Result<Value, KlvmError> {
+        loop {
+            match self.step_frame()? {
+                StepResult::Continue => {}
+                StepResult::Halted(value) => return Ok(value),
+                StepResult::Request(_) => {
+                    let message = "Actor instruction executed outside a scheduler";
+                    return Err(KlvmError::InternalError(message.to_string()));
+                }
+            }
+        }
+    }
+
+    /*
+     * Post-condition:
+     *   One instruction of the current frame is executed, or the frame returns `Null` if its
+     *   program counter is past the end of its body.
+     */
+    fn step_frame(&mut self) -> Result<StepResult, KlvmError> {
+        let frame = self.frames.last().unwrap();
+        // The instruction borrows from the function while executing it changes the frames
+        let func = Rc::clone(&frame.func);
+        match func.body.get(frame.pc) {
+            Some(instr) => self.execute_instr(instr),
+            None => Ok(self.return_value(Value::Null)),
+        }
+    }
+
+    /*
+     * Post-condition:
+     *   A `Bool` is truthy when true, and an `Int32` or `Int64` is truthy when non-zero, so that
+     *   the result of a comparison can drive a branch. Other types are a mismatch.
+     */
+    fn truthy(&self, name: &str) -> Result<bool, KlvmError> {
+        match self.value(name)? {
+            Value::Bool(v) => Ok(*v),
+            Value::Int32(v) => Ok(*v != 0),
+            Value::Int64(v) => Ok(*v != 0),
+            other => Err(KlvmError::TypeMismatch {
+                expected: "bool",
+                found: other.type_name(),
+            }),
+        }
+    }
+
+    fn value(&self, name: &str) -> Result<&Value, KlvmError> {
+        self.frames.last().unwrap().env.get(name)
+    }
+
+    fn values(&self, names: &[&str]) -> Result<Vec<Value>, KlvmError> {
+        names.iter().map(|n| self.value(n).cloned()).collect()
+    }
+}
+
+/// Maximum number of instructions an actor executes before the scheduler runs another actor,
+/// unless set otherwise.
+pub const DEFAULT_QUANTUM: usize = 1_000;
+
+/// Actor that asked for a message, and the binding in its machine that receives the reply.
+#[derive(Debug)]
+struct Asker {
+    actor: ActorRef,
+    reply_target: Box<str>,
+}
+
+/// Message in a mailbox. An ask carries the actor to reply to.
+#[derive(Debug)]
+struct Envelope {
+    message: Value,
+    asker: Option<Asker>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ActorStatus {
+    /// In the run queue.
+    Ready,
+    /// Suspended by `Receive` on an empty mailbox until a message binds to the target.
+    Receiving(Box<str>),
+    /// Suspended by `Ask` until the reply arrives.
+    Asking,
+}
+
+struct ActorState<'a> {
+    machine: Machine<'a>,
+    mailbox: Mailbox<Envelope>,
+    status: ActorStatus,
+    /// Askers of received messages that have not been replied to, most recent last. `Reply` answers
+    /// the last one.
+    askers: Vec<Asker>,
+    /// Error raised when the actor next runs, because an ask it is waiting on cannot be answered.
+    failure: Option<KlvmError>,
+}
+
+impl ActorState<'_> {
+    /*
+     * Post-condition:
+     *   `envelope.message` is bound to `target`, and its asker, if any, awaits a reply.
+     */
+    fn receive(&mut self, target: &str, envelope: Envelope) {
+        self.machine.bind(target, envelope.message);
+        self.askers.extend(envelope.asker);
+    }
+}
+
+/// Runs actors cooperatively on the current thread. Ready actors take turns from a run queue,
+/// each executing up to `quantum` instructions per turn. An actor leaves the queue while it waits
+/// for a message or a reply, and rejoins it when one is delivered. An actor whose body returns, or
+/// that raises an error, is terminated, and messages told to it are dropped.
+///
+/// The scheduler completes the requests made by actor instructions. A `Reply` answers the most
+/// recently received ask that has not been answered, and is dropped if there is none. An ask that
+/// cannot be answered, because its receiver has terminated or terminates before replying, raises
+/// `NoReply` in the asker.
+pub struct Scheduler<'a> {
+    actor_defs: HashMap<&'a str, ActorDef<'a>>,
+    actors: HashMap<ActorRef, ActorState<'a>>,
+    run_queue: VecDeque<ActorRef>,
+    next_id: u64,
+    quantum: usize,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new() -> Scheduler<'a> {
+        Scheduler {
+            actor_defs: HashMap::new(),
+            actors: HashMap::new(),
+            run_queue: VecDeque::new(),
+            next_id: 0,
+            quantum: DEFAULT_QUANTUM,
+        }
+    }
+
+    /// Define the actor definition that `SpawnActor` refers to as `name`.
+    pub fn define_actor(&mut self, name: &'a str, def: ActorDef<'a>) {
+        self.actor_defs.insert(name, def);
+    }
+
+    /// True until the actor terminates.
+    pub fn is_alive(&self, actor: ActorRef) -> bool {
+        self.actors.contains_key(&actor)
+    }
+
+    /// Look up `name` in the current frame of a live actor.
+    pub fn lookup(&self, actor: ActorRef, name: &str) -> Option<&Value> {
+        self.actors.get(&actor)?.machine.lookup(name)
+    }
+
+    pub fn quantum(&self) -> usize {
+        self.quantum
+    }
+
+    /// Run actors until none is ready. An actor that raises an error is terminated, and the error
+    /// is returned as an `ActorPanic` without running the remaining actors.
+    pub fn run_until_idle(&mut self) -> Result<(), KlvmError> {
+        while let Some(actor) = self.run_queue.pop_front() {
+            if let Err(error) = self.run_actor(actor) {
+                self.terminate(actor);
+                let error = Box::new(error);
+                return Err(KlvmError::ActorPanic { actor, error });
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the number of instructions per turn. A quantum of 0 is taken as 1, so that actors make
+    /// progress.
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.quantum = quantum.max(1);
+    }
+
+    /// Create an actor from `def` with `args` and make it ready.
+    pub fn spawn(&mut self, def: ActorDef<'a>, args: Vec<Value>) -> Result<ActorRef, KlvmError> {
+        if def.params.len() != args.len() {
+            return Err(KlvmError::ArityMismatch {
+                expected: def.params.len(),
+                found: args.len(),
+            });
+        }
+        let mut machine = Machine::new(def.body);
+        for (param, arg) in def.params.iter().zip(args) {
+            machine.bind(param, arg);
+        }
+        let actor = ActorRef::new(self.next_id);
+        self.next_id += 1;
+        let state = ActorState {
+            machine,
+            mailbox: Mailbox::new(),
+            status: ActorStatus::Ready,
+            askers: vec![],
+            failure: None,
+        };
+        self.actors.insert(actor, state);
+        self.run_queue.push_back(actor);
+        Ok(actor)
+    }
+
+    /// Send `message` to `actor` from outside the scheduler.
+    pub fn tell(&mut self, actor: ActorRef, message: Value) {
+        self.deliver(
+            actor,
+            Envelope {
+                message,
+                asker: None,
+            },
+        );
+    }
+
+    fn complete(&mut self, actor: ActorRef, request: ActorRequest) -> Result<(), KlvmError> {
+        match request {
+            ActorRequest::Ask {
+                actor: receiver,
+                message,
+                reply_target,
+            } => {
+                if !self.actors.contains_key(&receiver) {
+                    return Err(KlvmError::NoReply(receiver));
+                }
+                self.actors.get_mut(&actor).unwrap().status = ActorStatus::Asking;
+                let asker = Some(Asker {
+                    actor,
+                    reply_target,
+                });
+                self.deliver(receiver, Envelope { message, asker });
+            }
+            ActorRequest::Receive { target } => {
+                let state = self.actors.get_mut(&actor).unwrap();
+                match state.mailbox.pop_front() {
+                    Some(envelope) => state.receive(&target, envelope),
+                    None => state.status = ActorStatus::Receiving(target),
+                }
+            }
+            ActorRequest::Reply { value } => {
+                let state = self.actors.get_mut(&actor).unwrap();
+                let Some(asker) = state.askers.pop() else {
+                    return Ok(());
+                };
+                if let Some(state) = self.actors.get_mut(&asker.actor) {
+                    state.machine.bind(&asker.reply_target, value);
+                    state.status = ActorStatus::Ready;
+                    self.run_queue.push_back(asker.actor);
+                }
+            }
+            ActorRequest::Spawn {
+                actor_def,
+                args,
+                target,
+            } => {
+                let def = self
+                    .actor_defs
+                    .get(&*actor_def)
+                    .ok_or_else(|| KlvmError::UndefinedActor(actor_def.to_string()))?;
+                let spawned = self.spawn(def.clone(), args)?;
+                let state = self.actors.get_mut(&actor).unwrap();
+                state.machine.bind(&target, Value::ActorRef(spawned));
+            }
+            ActorRequest::Tell {
+                actor: receiver,
+                message,
+            } => self.tell(receiver, message),
+        }
+        Ok(())
+    }
+
+    /*
+     * Post-condition:
+     *   If `actor` is waiting for a message, `envelope` is received and the actor is made ready.
+     *   Otherwise, `envelope` is queued in its mailbox, or dropped if the actor has terminated.
+     */
+    fn deliver(&mut self, actor: ActorRef, envelope: Envelope) {
+        let Some(state) = self.actors.get_mut(&actor) else {
+            return;
+        };
+        match &state.status {
+            ActorStatus::Receiving(target) => {
+                let target = target.clone();
+                state.receive(&target, envelope);
+                state.status = ActorStatus::Ready;
+                self.run_queue.push_back(actor);
+            }
+            _ => state.mailbox.push_back(envelope),
+        }
+    }
+
+    /*
+     * Pre-condition:
+     *   `actor` is ready and was removed from the run queue.
+     *
+     * Post-condition:
+     *   The actor executed until it was suspended, terminated, or used its quantum. A ready actor
+     *   is queued again.
+     */
+    fn run_actor(&mut self, actor: ActorRef) -> Result<(), KlvmError> {
+        if let Some(error) = self.actors.get_mut(&actor).unwrap().failure.take() {
+            return Err(error);
+        }
+        for _ in 0..self.quantum {
+            let state = self.actors.get_mut(&actor).unwrap();
+            match state.machine.step()? {
+                StepResult::Continue => {}
+                StepResult::Halted(_) => {
+                    self.terminate(actor);
+                    return Ok(());
+                }
+                StepResult::Request(request) => self.complete(actor, request)?,
+            }
+            if self.actors[&actor].status != ActorStatus::Ready {
+                return Ok(());
+            }
+        }
+        self.run_queue.push_back(actor);
+        Ok(())
+    }
+
+    /*
+     * Post-condition:
+     *   `actor` is removed. Each actor waiting on an ask that `actor` will never answer is made
+     *   ready to raise `NoReply`.
+     */
+    fn terminate(&mut self, actor: ActorRef) {
+        let Some(mut state) = self.actors.remove(&actor) else {
+            return;
+        };
+        let mut askers = state.askers;
+        while let Some(envelope) = state.mailbox.pop_front() {
+            askers.extend(envelope.asker);
+        }
+        for asker in askers {
+            if let Some(state) = self.actors.get_mut(&asker.actor) {
+                state.failure = Some(KlvmError::NoReply(actor));
+                state.status = ActorStatus::Ready;
+                self.run_queue.push_back(asker.actor);
+            }
+        }
+    }
+}
+
+impl<'a> Default for Scheduler<'a> {
+    fn default() -> Scheduler<'a> {
+        Scheduler::new()
+    }
+}
 
 enum ComputeAdvice {
     Preempt,
@@ -15,3 +1234,1775 @@ enum ComputeAdvice {
     Halt,
     Finish,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::klvm::value::Scalar;
+
+    fn program(instrs: Vec<Instr>) -> Program {
+        Program::new(instrs).unwrap()
+    }
+
+    fn int(i: i32) -> Value {
+        Value::Int32(i)
+    }
+
+    fn pc(machine: &Machine) -> usize {
+        machine.frames.last().unwrap().pc
+    }
+
+    fn run<'a>(machine: &mut Machine<'a>, instrs: &[Instr<'a>]) -> Result<(), KlvmError> {
+        for instr in instrs {
+            machine.execute_instr(instr)?;
+        }
+        Ok(())
+    }
+
+    /*
+     * Execute `instr` with `left` and `right` bound, and return the value bound to `target`.
+     */
+    fn eval(instr: Instr, left: Value, right: Value) -> Result<Value, KlvmError> {
+        let mut machine = Machine::new(Program::default());
+        machine.bind("a", left);
+        machine.bind("b", right);
+        machine.execute_instr(&instr)?;
+        Ok(machine.lookup("c").unwrap().clone())
+    }
+
+    /*
+     * The instruction for `c = a op b` on `Int32` operands if `int64` is false, or on `Int64`
+     * operands if it is true.
+     */
+    fn int_instr(op: char, int64: bool) -> Instr<'static> {
+        let (left, right, target) = ("a", "b", "c");
+        match (op, int64) {
+            ('+', false) => Instr::AddInt32 {
+                left,
+                right,
+                target,
+            },
+            ('-', false) => Instr::SubInt32 {
+                left,
+                right,
+                target,
+            },
+            ('*', false) => Instr::MulInt32 {
+                left,
+                right,
+                target,
+            },
+            ('/', false) => Instr::DivInt32 {
+                left,
+                right,
+                target,
+            },
+            ('%', false) => Instr::RemInt32 {
+                left,
+                right,
+                target,
+            },
+            ('+', true) => Instr::AddInt64 {
+                left,
+                right,
+                target,
+            },
+            ('-', true) => Instr::SubInt64 {
+                left,
+                right,
+                target,
+            },
+            ('*', true) => Instr::MulInt64 {
+                left,
+                right,
+                target,
+            },
+            ('/', true) => Instr::DivInt64 {
+                left,
+                right,
+                target,
+            },
+            ('%', true) => Instr::RemInt64 {
+                left,
+                right,
+                target,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_int32_arithmetic() {
+        let overflow = Err(KlvmError::ArithmeticOverflow);
+        let zero = Err(KlvmError::DivisionByZero);
+        let cases = [
+            ('+', 7, 2, Ok(9)),
+            ('-', 7, 2, Ok(5)),
+            ('*', 7, 2, Ok(14)),
+            ('/', -7, 2, Ok(-3)),
+            ('%', -7, 2, Ok(-1)),
+            ('+', i32::MAX, 1, overflow.clone()),
+            ('-', i32::MIN, 1, overflow.clone()),
+            ('*', i32::MAX, 2, overflow.clone()),
+            ('/', i32::MIN, -1, overflow.clone()),
+            ('%', i32::MIN, -1, overflow),
+            ('/', 1, 0, zero.clone()),
+            ('%', 1, 0, zero),
+        ];
+        for (op, left, right, expected) in cases {
+            let answer = eval(int_instr(op, false), int(left), int(right));
+            assert_eq!(
+                expected.map(Value::Int32),
+                answer,
+                "{} {} {}",
+                left,
+                op,
+                right
+            );
+        }
+    }
+
+    #[test]
+    fn test_int64_arithmetic() {
+        let overflow = Err(KlvmError::ArithmeticOverflow);
+        let zero = Err(KlvmError::DivisionByZero);
+        let cases = [
+            ('+', 1 << 40, 2, Ok((1 << 40) + 2)),
+            ('-', 7, 9, Ok(-2)),
+            ('*', 1 << 40, 4, Ok(1 << 42)),
+            ('/', 1 << 40, -4, Ok(-(1 << 38))),
+            ('%', 7, -4, Ok(3)),
+            ('+', i64::MAX, 1, overflow.clone()),
+            ('-', i64::MIN, 1, overflow.clone()),
+            ('*', i64::MIN, -1, overflow.clone()),
+            ('/', i64::MIN, -1, overflow),
+            ('/', 1, 0, zero.clone()),
+            ('%', 1, 0, zero),
+        ];
+        for (op, left, right, expected) in cases {
+            let answer = eval(int_instr(op, true), Value::Int64(left), Value::Int64(right));
+            assert_eq!(
+                expected.map(Value::Int64),
+                answer,
+                "{} {} {}",
+                left,
+                op,
+                right
+            );
+        }
+    }
+
+    /*
+     * The instruction for `c = a op b` on `Flt32` operands if `flt64` is false, or on `Flt64`
+     * operands if it is true.
+     */
+    fn flt_instr(op: char, flt64: bool) -> Instr<'static> {
+        let (left, right, target) = ("a", "b", "c");
+        match (op, flt64) {
+            ('+', false) => Instr::AddFlt32 {
+                left,
+                right,
+                target,
+            },
+            ('-', false) => Instr::SubFlt32 {
+                left,
+                right,
+                target,
+            },
+            ('*', false) => Instr::MulFlt32 {
+                left,
+                right,
+                target,
+            },
+            ('/', false) => Instr::DivFlt32 {
+                left,
+                right,
+                target,
+            },
+            ('+', true) => Instr::AddFlt64 {
+                left,
+                right,
+                target,
+            },
+            ('-', true) => Instr::SubFlt64 {
+                left,
+                right,
+                target,
+            },
+            ('*', true) => Instr::MulFlt64 {
+                left,
+                right,
+                target,
+            },
+            ('/', true) => Instr::DivFlt64 {
+                left,
+                right,
+                target,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_flt32_arithmetic() {
+        let cases = [
+            ('+', 1.5, 2.25, 3.75),
+            ('-', 1.5, 2.25, -0.75),
+            ('*', 1.5, -2.0, -3.0),
+            ('/', 3.0, 2.0, 1.5),
+            ('/', 1.0, 0.0, f32::INFINITY),
+            ('/', -1.0, 0.0, f32::NEG_INFINITY),
+            ('*', f32::MAX, 2.0, f32::INFINITY),
+        ];
+        for (op, left, right, expected) in cases {
+            let answer = eval(
+                flt_instr(op, false),
+                Value::Flt32(left),
+                Value::Flt32(right),
+            );
+            assert_eq!(
+                Ok(Value::Flt32(expected)),
+                answer,
+                "{} {} {}",
+                left,
+                op,
+                right
+            );
+        }
+        let answer = eval(flt_instr('/', false), Value::Flt32(0.0), Value::Flt32(0.0));
+        assert!(answer.unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_flt64_arithmetic() {
+        let cases = [
+            ('+', 1.5, 2.25, 3.75),
+            ('-', 1.5, 2.25, -0.75),
+            ('*', 1.5, -2.0, -3.0),
+            ('/', 3.0, 2.0, 1.5),
+            ('/', 1.0, 0.0, f64::INFINITY),
+            ('/', -1.0, 0.0, f64::NEG_INFINITY),
+        ];
+        for (op, left, right, expected) in cases {
+            let answer = eval(flt_instr(op, true), Value::Flt64(left), Value::Flt64(right));
+            assert_eq!(
+                Ok(Value::Flt64(expected)),
+                answer,
+                "{} {} {}",
+                left,
+                op,
+                right
+            );
+        }
+        let inf = Value::Flt64(f64::INFINITY);
+        let answer = eval(flt_instr('-', true), inf.clone(), inf);
+        assert!(answer.unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_flt_unary() {
+        let (operand, target) = ("a", "c");
+        let neg32 = Instr::NegFlt32 { operand, target };
+        let answer = eval(neg32, Value::Flt32(1.5), Value::Null);
+        assert_eq!(Ok(Value::Flt32(-1.5)), answer);
+        let neg64 = Instr::NegFlt64 { operand, target };
+        let answer = eval(neg64, Value::Flt64(-1.5), Value::Null);
+        assert_eq!(Ok(Value::Flt64(1.5)), answer);
+        let abs = Instr::AbsFlt64 { operand, target };
+        let answer = eval(abs, Value::Flt64(-2.5), Value::Null);
+        assert_eq!(Ok(Value::Flt64(2.5)), answer);
+        let sqrt = Instr::SqrtFlt64 { operand, target };
+        let answer = eval(sqrt.clone(), Value::Flt64(6.25), Value::Null);
+        assert_eq!(Ok(Value::Flt64(2.5)), answer);
+        let answer = eval(sqrt, Value::Flt64(-1.0), Value::Null);
+        assert!(answer.unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_flt_arithmetic_errors() {
+        let answer = eval(flt_instr('+', true), Value::Flt32(1.0), Value::Flt64(1.0));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "f64",
+                found: "f32"
+            }),
+            answer
+        );
+        let answer = eval(flt_instr('/', false), Value::Flt32(1.0), int(0));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "f32",
+                found: "i32"
+            }),
+            answer
+        );
+    }
+
+    /*
+     * The instruction for `c = a op b` on `Dec` operands.
+     */
+    fn dec_instr(op: char) -> Instr<'static> {
+        let (left, right, target) = ("a", "b", "c");
+        match op {
+            '+' => Instr::AddDec {
+                left,
+                right,
+                target,
+            },
+            '-' => Instr::SubDec {
+                left,
+                right,
+                target,
+            },
+            '*' => Instr::MulDec {
+                left,
+                right,
+                target,
+            },
+            '/' => Instr::DivDec {
+                left,
+                right,
+                target,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_dec_arithmetic() {
+        let dec = |s: &str| Value::Dec(s.parse().unwrap());
+        let cases = [
+            ('+', "1.1m", "2.2m", "3.3m"),
+            ('-', "1.1m", "2.25m", "-1.15m"),
+            ('*', "1.1m", "2.2m", "2.42m"),
+            ('/', "1.1m", "2.2m", "0.5m"),
+            ('/', "10m", "4m", "2.5m"),
+        ];
+        for (op, left, right, expected) in cases {
+            let answer = eval(dec_instr(op), dec(left), dec(right)).unwrap();
+            assert_eq!(expected, answer.to_string(), "{} {} {}", left, op, right);
+        }
+        let answer = eval(dec_instr('/'), dec("1m"), dec("0.0m"));
+        assert_eq!(Err(KlvmError::DivisionByZero), answer);
+        let max = Value::Dec(Decimal::new(i128::MAX, 0));
+        let answer = eval(dec_instr('+'), max.clone(), dec("1m"));
+        assert_eq!(Err(KlvmError::ArithmeticOverflow), answer);
+        let answer = eval(dec_instr('*'), max, dec("2m"));
+        assert_eq!(Err(KlvmError::ArithmeticOverflow), answer);
+        let answer = eval(dec_instr('+'), dec("1m"), Value::Flt64(1.0));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "dec",
+                found: "f64"
+            }),
+            answer
+        );
+    }
+
+    #[test]
+    fn test_int_negation() {
+        let neg32 = Instr::NegInt32 {
+            operand: "a",
+            target: "c",
+        };
+        assert_eq!(Ok(int(-5)), eval(neg32.clone(), int(5), Value::Null));
+        let answer = eval(neg32, int(i32::MIN), Value::Null);
+        assert_eq!(Err(KlvmError::ArithmeticOverflow), answer);
+        let neg64 = Instr::NegInt64 {
+            operand: "a",
+            target: "c",
+        };
+        let answer = eval(neg64.clone(), Value::Int64(-5), Value::Null);
+        assert_eq!(Ok(Value::Int64(5)), answer);
+        let answer = eval(neg64, Value::Int64(i64::MIN), Value::Null);
+        assert_eq!(Err(KlvmError::ArithmeticOverflow), answer);
+    }
+
+    #[test]
+    fn test_int_arithmetic_errors() {
+        let answer = eval(int_instr('+', false), int(1), Value::Int64(1));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "i32",
+                found: "i64"
+            }),
+            answer
+        );
+        let answer = eval(int_instr('/', true), Value::Int64(1), int(0));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "i64",
+                found: "i32"
+            }),
+            answer
+        );
+        let sub = Instr::SubInt32 {
+            left: "x",
+            right: "b",
+            target: "c",
+        };
+        let answer = eval(sub, int(1), int(1));
+        assert_eq!(Err(KlvmError::UndefinedVariable("x".to_string())), answer);
+        // A failed instruction leaves its target unbound
+        let mut machine = Machine::new(Program::default());
+        machine.bind("a", int(1));
+        machine.bind("b", int(0));
+        let rem = int_instr('%', false);
+        assert_eq!(Err(KlvmError::DivisionByZero), machine.execute_instr(&rem));
+        assert_eq!(None, machine.lookup("c"));
+        assert_eq!(0, pc(&machine));
+    }
+
+    #[test]
+    fn test_load_and_store() {
+        let mut machine = Machine::new(Program::default());
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(1),
+                target: "x",
+            },
+            Instr::LoadConst {
+                value: Scalar::Str("a".into()),
+                target: "s",
+            },
+            Instr::LoadLocal {
+                name: "x",
+                target: "y",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(41),
+                target: "k",
+            },
+            Instr::AddInt32 {
+                left: "k",
+                right: "y",
+                target: "z",
+            },
+            Instr::StoreLocal {
+                source: "z",
+                name: "x",
+            },
+        ];
+        run(&mut machine, &instrs).unwrap();
+        assert_eq!(Some(&Value::Int32(42)), machine.lookup("x"));
+        assert_eq!(Some(&Value::Int32(1)), machine.lookup("y"));
+        assert_eq!(Some(&Value::Str("a".into())), machine.lookup("s"));
+    }
+
+    #[test]
+    fn test_load_and_store_errors() {
+        let mut machine = Machine::new(Program::default());
+        let load = Instr::LoadLocal {
+            name: "x",
+            target: "y",
+        };
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("x".to_string())),
+            machine.execute_instr(&load)
+        );
+        // A store updates a local and cannot introduce one
+        machine.bind("z", Value::Null);
+        let store = Instr::StoreLocal {
+            source: "z",
+            name: "x",
+        };
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("x".to_string())),
+            machine.execute_instr(&store)
+        );
+        assert_eq!(None, machine.lookup("x"));
+    }
+
+    #[test]
+    fn test_cmp_int32() {
+        let mut machine = Machine::new(Program::default());
+        machine.bind("a", Value::Int32(1));
+        machine.bind("b", Value::Int32(2));
+        for (left, right, expected) in [("a", "b", -1), ("a", "a", 0), ("b", "a", 1)] {
+            let instr = Instr::CmpInt32 {
+                left,
+                right,
+                target: "c",
+            };
+            machine.execute_instr(&instr).unwrap();
+            assert_eq!(Some(&Value::Int32(expected)), machine.lookup("c"));
+        }
+    }
+
+    #[test]
+    fn test_jumps() {
+        let mut machine = Machine::new(Program::default());
+        machine.bind("yes", Value::Bool(true));
+        machine.bind("no", Value::Bool(false));
+        machine.execute_instr(&Instr::Jump { offset: 3 }).unwrap();
+        assert_eq!(3, pc(&machine));
+        machine.execute_instr(&Instr::Jump { offset: -2 }).unwrap();
+        assert_eq!(1, pc(&machine));
+        let jump_if = |condition| Instr::JumpIf {
+            condition,
+            offset: 5,
+        };
+        machine.execute_instr(&jump_if("yes")).unwrap();
+        assert_eq!(6, pc(&machine));
+        machine.execute_instr(&jump_if("no")).unwrap();
+        assert_eq!(7, pc(&machine));
+        machine.bind("zero", Value::Int32(0));
+        machine.execute_instr(&jump_if("zero")).unwrap();
+        assert_eq!(8, pc(&machine));
+        machine.bind("less", Value::Int64(-1));
+        machine.execute_instr(&jump_if("less")).unwrap();
+        assert_eq!(13, pc(&machine));
+        machine.bind("s", Value::Str("".into()));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "bool",
+                found: "str"
+            }),
+            machine.execute_instr(&jump_if("s"))
+        );
+        // Labels must be resolved before execution
+        assert_eq!(
+            Err(KlvmError::UndefinedLabel("top".to_string())),
+            machine.execute_instr(&Instr::JumpLabel { label: "top" })
+        );
+    }
+
+    #[test]
+    fn test_loop() {
+        // sum = 0; i = 0; while i != 10 { sum += 2; i += 1 }
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(0),
+                target: "sum",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(0),
+                target: "i",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(10),
+                target: "n",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(1),
+                target: "one",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(2),
+                target: "two",
+            },
+            Instr::Label { name: "top".into() },
+            Instr::CmpInt32 {
+                left: "i",
+                right: "n",
+                target: "c",
+            },
+            Instr::JumpIfLabel {
+                condition: "c",
+                label: "body",
+            },
+            Instr::JumpLabel { label: "end" },
+            Instr::Label {
+                name: "body".into(),
+            },
+            Instr::AddInt32 {
+                left: "sum",
+                right: "two",
+                target: "sum",
+            },
+            Instr::AddInt32 {
+                left: "i",
+                right: "one",
+                target: "i",
+            },
+            Instr::JumpLabel { label: "top" },
+            Instr::Label { name: "end".into() },
+            Instr::Return { value: "sum" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        assert_eq!(Ok(Value::Int32(20)), machine.execute());
+    }
+
+    /*
+     * count(n, acc) returns acc + 2 * n. The recursion is a tail call, so the stack does not grow.
+     */
+    fn count_function() -> Function<'static> {
+        Function::new(
+            vec!["n", "acc"],
+            program(vec![
+                Instr::LoadConst {
+                    value: Scalar::Int32(1),
+                    target: "one",
+                },
+                Instr::LoadConst {
+                    value: Scalar::Int32(0),
+                    target: "zero",
+                },
+                Instr::CmpInt32 {
+                    left: "n",
+                    right: "zero",
+                    target: "c",
+                },
+                Instr::JumpIf {
+                    condition: "c",
+                    offset: 2,
+                },
+                Instr::Return { value: "acc" },
+                Instr::SubInt32 {
+                    left: "n",
+                    right: "one",
+                    target: "n",
+                },
+                Instr::LoadConst {
+                    value: Scalar::Int32(2),
+                    target: "two",
+                },
+                Instr::AddInt32 {
+                    left: "acc",
+                    right: "two",
+                    target: "acc",
+                },
+                Instr::TailCall {
+                    func: "count",
+                    args: vec!["n", "acc"],
+                },
+            ]),
+        )
+    }
+
+    /*
+     * depth(n) = if n == 0 then 0 else depth(n - 1) + 1, which needs n + 1 frames.
+     */
+    fn depth_function() -> Function<'static> {
+        Function::new(
+            vec!["n"],
+            program(vec![
+                Instr::LoadConst {
+                    value: Scalar::Int32(1),
+                    target: "one",
+                },
+                Instr::LoadConst {
+                    value: Scalar::Int32(0),
+                    target: "zero",
+                },
+                Instr::CmpInt32 {
+                    left: "n",
+                    right: "zero",
+                    target: "c",
+                },
+                Instr::JumpIf {
+                    condition: "c",
+                    offset: 2,
+                },
+                Instr::Return { value: "zero" },
+                Instr::SubInt32 {
+                    left: "n",
+                    right: "one",
+                    target: "n",
+                },
+                Instr::Call {
+                    func: "depth",
+                    args: vec!["n"],
+                    target: "r",
+                },
+                Instr::AddInt32 {
+                    left: "r",
+                    right: "one",
+                    target: "r",
+                },
+                Instr::Return { value: "r" },
+            ]),
+        )
+    }
+
+    #[test]
+    fn test_call() {
+        let mut machine = Machine::new(Program::default());
+        machine.define_function("depth", depth_function());
+        assert_eq!(Ok(Value::Int32(10)), machine.call("depth", vec![int(10)]));
+        assert_eq!(1, machine.frames.len());
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut machine = Machine::new(Program::default());
+        machine.define_function("count", count_function());
+        assert_eq!(
+            Err(KlvmError::ArityMismatch {
+                expected: 2,
+                found: 1
+            }),
+            machine.call("count", vec![int(1)])
+        );
+        assert_eq!(
+            Err(KlvmError::UndefinedFunction("other".to_string())),
+            machine.call("other", vec![])
+        );
+        // An error inside the callee discards its frames
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "i32",
+                found: "null"
+            }),
+            machine.call("count", vec![int(1), Value::Null])
+        );
+        assert_eq!(1, machine.frames.len());
+        // Running past the end returns null
+        machine.define_function("empty", Function::new(vec![], Program::default()));
+        assert_eq!(Ok(Value::Null), machine.call("empty", vec![]));
+    }
+
+    #[test]
+    fn test_call_instr() {
+        let mut machine = Machine::new(Program::default());
+        machine.define_function("count", count_function());
+        machine.bind("n", int(3));
+        machine.bind("acc", int(0));
+        let instrs = vec![
+            Instr::Call {
+                func: "count",
+                args: vec!["n", "acc"],
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+        ];
+        machine.define_function("main", Function::new(vec!["n", "acc"], program(instrs)));
+        assert_eq!(
+            Ok(Value::Int32(6)),
+            machine.call("main", vec![int(3), int(0)])
+        );
+        // The root frame is not popped by a return
+        assert_eq!(
+            Ok(StepResult::Halted(Value::Int32(3))),
+            machine.execute_instr(&Instr::Return { value: "n" })
+        );
+        assert_eq!(Some(&Value::Int32(3)), machine.lookup("n"));
+    }
+
+    #[test]
+    fn test_tail_call() {
+        let mut machine = Machine::new(Program::default());
+        machine.define_function("count", count_function());
+        let answer = machine.call("count", vec![int(100_000), int(0)]);
+        assert_eq!(Ok(Value::Int32(200_000)), answer);
+    }
+
+    /*
+     * acc = 1; while n != 1 { acc = acc * n; n = n - 1 }; return acc
+     */
+    fn factorial_program(n: i32) -> Program<'static> {
+        program(vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(n),
+                target: "n",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(1),
+                target: "acc",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(1),
+                target: "one",
+            },
+            Instr::Label { name: "top".into() },
+            Instr::CmpInt32 {
+                left: "n",
+                right: "one",
+                target: "c",
+            },
+            Instr::JumpIfLabel {
+                condition: "c",
+                label: "body",
+            },
+            Instr::Return { value: "acc" },
+            Instr::Label {
+                name: "body".into(),
+            },
+            Instr::MulInt32 {
+                left: "acc",
+                right: "n",
+                target: "acc",
+            },
+            Instr::SubInt32 {
+                left: "n",
+                right: "one",
+                target: "n",
+            },
+            Instr::JumpLabel { label: "top" },
+        ])
+    }
+
+    #[test]
+    fn test_execute_factorial() {
+        let mut machine = Machine::new(factorial_program(10));
+        assert_eq!(Ok(Value::Int32(3_628_800)), machine.execute());
+        let mut machine = Machine::new(factorial_program(13));
+        assert_eq!(Err(KlvmError::ArithmeticOverflow), machine.execute());
+    }
+
+    #[test]
+    fn test_execute_recursive_factorial() {
+        // fact(n) = if n == 1 then 1 else n * fact(n - 1)
+        let fact = program(vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(1),
+                target: "one",
+            },
+            Instr::CmpInt32 {
+                left: "n",
+                right: "one",
+                target: "c",
+            },
+            Instr::JumpIf {
+                condition: "c",
+                offset: 2,
+            },
+            Instr::Return { value: "one" },
+            Instr::SubInt32 {
+                left: "n",
+                right: "one",
+                target: "m",
+            },
+            Instr::Call {
+                func: "fact",
+                args: vec!["m"],
+                target: "r",
+            },
+            Instr::MulInt32 {
+                left: "n",
+                right: "r",
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+        ]);
+        let main = program(vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(6),
+                target: "n",
+            },
+            Instr::Call {
+                func: "fact",
+                args: vec!["n"],
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+        ]);
+        let mut machine = Machine::new(main);
+        machine.define_function("fact", Function::new(vec!["n"], fact));
+        assert_eq!(Ok(Value::Int32(720)), machine.execute());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut machine = Machine::new(factorial_program(2));
+        let mut steps = 1;
+        while machine.step() == Ok(StepResult::Continue) {
+            steps += 1;
+        }
+        // 3 loads, 5 for the iteration with n = 2, and 3 to test n = 1 and return
+        assert_eq!(11, steps);
+        assert_eq!(Ok(StepResult::Halted(Value::Int32(2))), machine.step());
+        // A program that runs past its end halts with null
+        let mut machine = Machine::new(Program::default());
+        assert_eq!(Ok(StepResult::Halted(Value::Null)), machine.step());
+    }
+
+    #[test]
+    fn test_frames() {
+        let main = program(vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(2),
+                target: "n",
+            },
+            Instr::Call {
+                func: "depth",
+                args: vec!["n"],
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+        ]);
+        let mut machine = Machine::new(main);
+        machine.define_function("depth", depth_function());
+        // Step into the innermost call of depth
+        while machine.frames().len() < 4 {
+            machine.step().unwrap();
+        }
+        let names: Vec<&str> = machine.frames().iter().map(|f| &*f.func_name).collect();
+        assert_eq!(vec!["$root", "depth", "depth", "depth"], names);
+        let frame = &machine.frames()[1];
+        assert_eq!(Some("r"), frame.return_target.as_deref());
+        assert_eq!(7, frame.pc);
+        assert_eq!(Some(&Value::Int32(1)), frame.env.lookup("n"));
+        assert_eq!(Ok(Value::Int32(2)), machine.execute());
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut machine = Machine::new(Program::default());
+        assert_eq!(DEFAULT_OVERFLOW_LIMIT, machine.overflow_limit());
+        machine.define_function("count", count_function());
+        machine.define_function("depth", depth_function());
+        machine.set_overflow_limit(50);
+        // The root frame and depth(0) through depth(48)
+        assert_eq!(Ok(Value::Int32(48)), machine.call("depth", vec![int(48)]));
+        assert_eq!(
+            Err(KlvmError::StackOverflow),
+            machine.call("depth", vec![int(49)])
+        );
+        assert_eq!(1, machine.frames().len());
+        // A tail call reuses its frame
+        machine.set_overflow_limit(2);
+        let answer = machine.call("count", vec![int(1000), int(0)]);
+        assert_eq!(Ok(Value::Int32(2000)), answer);
+    }
+
+    #[test]
+    fn test_call_native() {
+        // return $bit_test($bit_set(0, 3), 3)
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(0),
+                target: "zero",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(3),
+                target: "three",
+            },
+            Instr::Call {
+                func: "$bit_set",
+                args: vec!["zero", "three"],
+                target: "eight",
+            },
+            Instr::Call {
+                func: "$bit_test",
+                args: vec!["eight", "three"],
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+        ];
+        let mut machine = Machine::new(program(instrs.clone()));
+        assert_eq!(Ok(Value::Bool(true)), machine.execute());
+        assert_eq!(Some(&int(8)), machine.lookup("eight"));
+        assert_eq!(1, machine.frames().len());
+        // A native replaces a function of the same name
+        fn first(args: &[Value]) -> Result<Value, KlvmError> {
+            Ok(args[0].clone())
+        }
+        let mut machine = Machine::new(program(instrs));
+        machine.define_function("$bit_test", count_function());
+        machine.define_native("$bit_test", first);
+        assert_eq!(Ok(int(8)), machine.execute());
+        let mut machine = Machine::new(Program::default());
+        machine.bind("a", int(1));
+        machine.bind("b", int(40));
+        let call = Instr::Call {
+            func: "$bit_flip",
+            args: vec!["a", "b"],
+            target: "c",
+        };
+        assert_eq!(
+            Err(KlvmError::BitIndexOutOfRange { max: 32 }),
+            machine.execute_instr(&call)
+        );
+        // Every machine also defines the tuple natives
+        let tuple = Value::Tuple(Tuple::new(vec![int(1)]));
+        machine.bind("a", tuple.clone());
+        machine.bind("b", tuple);
+        let call = Instr::Call {
+            func: "$tuple_zip",
+            args: vec!["a", "b"],
+            target: "c",
+        };
+        assert!(machine.execute_instr(&call).is_ok());
+        assert_eq!("((1, 1))", machine.lookup("c").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        // r = {name: "Alice"}; t = (name, name); return t
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Str("Alice".into()),
+                target: "name",
+            },
+            Instr::CreateRec { target: "r" },
+            Instr::SetField {
+                rec: "r",
+                field: "name".into(),
+                value: "name",
+            },
+            Instr::CreateTuple {
+                elements: vec!["name", "name"],
+                target: "t",
+            },
+            Instr::Return { value: "t" },
+        ];
+        let mut machine = Machine::new(program(instrs.clone()));
+        assert_eq!(DEFAULT_MEMORY_LIMIT, machine.memory_limit());
+        assert_eq!(0, machine.memory_used());
+        assert!(machine.execute().is_ok());
+        // The field value and both tuple elements are counted
+        let used = machine.memory_used();
+        assert!(used >= 3 * "Alice".len());
+        let mut machine = Machine::new(program(instrs));
+        machine.set_memory_limit(used - 1);
+        assert_eq!(
+            Err(KlvmError::MemoryExceeded {
+                used,
+                limit: used - 1
+            }),
+            machine.execute()
+        );
+        // The allocation that failed is not counted
+        assert!(machine.memory_used() < used);
+        let mut machine = Machine::new(Program::default());
+        machine.set_memory_limit(4);
+        assert_eq!(Ok(()), machine.allocate_check(&Value::Int32(1)));
+        assert_eq!(4, machine.memory_used());
+        assert_eq!(
+            Err(KlvmError::MemoryExceeded { used: 8, limit: 4 }),
+            machine.allocate_check(&Value::Bool(true))
+        );
+        assert_eq!(4, machine.memory_used());
+    }
+
+    #[test]
+    fn test_record_literal() {
+        // r = {a: 1, b: 'x'}; r.a = 2; return r
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(1),
+                target: "one",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(2),
+                target: "two",
+            },
+            Instr::LoadConst {
+                value: Scalar::Char('x'),
+                target: "x",
+            },
+            Instr::CreateRec { target: "r" },
+            Instr::SetField {
+                rec: "r",
+                field: "a".into(),
+                value: "one",
+            },
+            Instr::SetField {
+                rec: "r",
+                field: "b".into(),
+                value: "x",
+            },
+            Instr::GetField {
+                rec: "r",
+                field: "a".into(),
+                target: "a",
+            },
+            Instr::SetField {
+                rec: "r",
+                field: "a".into(),
+                value: "two",
+            },
+            Instr::Return { value: "r" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        let mut expected = Rec::new();
+        expected.set("a", int(2));
+        expected.set("b", Value::Char('x'));
+        assert_eq!(Ok(Value::Rec(expected)), machine.execute());
+        // GetField copies the field's value at the time of the read
+        assert_eq!(Some(&int(1)), machine.lookup("a"));
+        let Some(Value::Rec(r)) = machine.lookup("r") else {
+            panic!("r is not a record");
+        };
+        let names: Vec<&str> = r.fields().map(|(name, _)| name).collect();
+        assert_eq!(vec!["a", "b"], names);
+    }
+
+    #[test]
+    fn test_record_errors() {
+        let mut machine = Machine::new(Program::default());
+        machine
+            .execute_instr(&Instr::CreateRec { target: "r" })
+            .unwrap();
+        let get = |rec, field: &str| Instr::GetField {
+            rec,
+            field: field.into(),
+            target: "t",
+        };
+        assert_eq!(
+            Err(KlvmError::UndefinedField("a".to_string())),
+            machine.execute_instr(&get("r", "a"))
+        );
+        machine.bind("n", Value::Null);
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "rec",
+                found: "null"
+            }),
+            machine.execute_instr(&get("n", "a"))
+        );
+        let set = |rec| Instr::SetField {
+            rec,
+            field: "a".into(),
+            value: "n",
+        };
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("s".to_string())),
+            machine.execute_instr(&set("s"))
+        );
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "rec",
+                found: "null"
+            }),
+            machine.execute_instr(&set("n"))
+        );
+    }
+
+    fn load_str(value: &str, target: &'static str) -> Instr<'static> {
+        Instr::LoadConst {
+            value: Scalar::Str(value.into()),
+            target,
+        }
+    }
+
+    fn setup_catch(handler_offset: i32) -> Instr<'static> {
+        Instr::SetupCatch {
+            handler_offset,
+            bind_name: "e".into(),
+        }
+    }
+
+    #[test]
+    fn test_throw_and_catch() {
+        // try { throw 'boom' } catch e { return e }; return 'ok'
+        let instrs = vec![
+            load_str("boom", "msg"),
+            load_str("ok", "ok"),
+            setup_catch(4),
+            Instr::Throw { value: "msg" },
+            Instr::PopCatch,
+            Instr::Return { value: "ok" },
+            Instr::Return { value: "e" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        assert_eq!(Ok(Value::Str("boom".into())), machine.execute());
+        // Without the throw, the region completes normally
+        let instrs = vec![
+            load_str("ok", "ok"),
+            setup_catch(3),
+            Instr::PopCatch,
+            Instr::Return { value: "ok" },
+            Instr::Return { value: "e" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        assert_eq!(Ok(Value::Str("ok".into())), machine.execute());
+        assert!(machine.handlers.is_empty());
+    }
+
+    #[test]
+    fn test_catch_with_labels() {
+        // The handler offset addresses the instructions before the label is removed
+        let instrs = vec![
+            setup_catch(4),
+            Instr::Label { name: "l".into() },
+            Instr::LoadConst {
+                value: Scalar::Int32(7),
+                target: "v",
+            },
+            Instr::Throw { value: "v" },
+            Instr::Return { value: "e" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        assert_eq!(Ok(int(7)), machine.execute());
+    }
+
+    #[test]
+    fn test_throw_unwinds_frames() {
+        let fail = Function::new(
+            vec!["x"],
+            program(vec![
+                Instr::Call {
+                    func: "throw",
+                    args: vec!["x"],
+                    target: "r",
+                },
+                Instr::Return { value: "r" },
+            ]),
+        );
+        let throw = Function::new(vec!["x"], program(vec![Instr::Throw { value: "x" }]));
+        let instrs = vec![
+            load_str("boom", "msg"),
+            setup_catch(4),
+            Instr::Call {
+                func: "fail",
+                args: vec!["msg"],
+                target: "r",
+            },
+            Instr::PopCatch,
+            Instr::Return { value: "r" },
+            Instr::Return { value: "e" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        machine.define_function("fail", fail);
+        machine.define_function("throw", throw);
+        assert_eq!(Ok(Value::Str("boom".into())), machine.execute());
+        assert_eq!(1, machine.frames().len());
+        assert!(machine.handlers.is_empty());
+    }
+
+    #[test]
+    fn test_uncaught() {
+        let instrs = vec![
+            load_str("boom", "msg"),
+            setup_catch(3),
+            Instr::PopCatch,
+            Instr::Throw { value: "msg" },
+            Instr::Return { value: "e" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        let uncaught = Err(KlvmError::Uncaught(Value::Str("boom".into())));
+        assert_eq!(uncaught, machine.execute());
+        // A handler is discarded when its frame returns
+        let leaky = Function::new(
+            vec![],
+            program(vec![
+                load_str("done", "r"),
+                setup_catch(2),
+                Instr::Return { value: "r" },
+                Instr::Return { value: "e" },
+            ]),
+        );
+        let instrs = vec![
+            load_str("boom", "msg"),
+            Instr::Call {
+                func: "leaky",
+                args: vec![],
+                target: "r",
+            },
+            Instr::Throw { value: "msg" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        machine.define_function("leaky", leaky);
+        assert_eq!(uncaught, machine.execute());
+        assert_eq!(Some(&Value::Str("done".into())), machine.lookup("r"));
+    }
+
+    #[test]
+    fn test_catch_errors() {
+        let mut machine = Machine::new(Program::default());
+        assert_eq!(
+            Err(KlvmError::InternalError(
+                "PopCatch without a handler in the current frame".to_string()
+            )),
+            machine.execute_instr(&Instr::PopCatch)
+        );
+        // A handler set up outside a call cannot catch a value thrown within it
+        machine.bind("e", Value::Null);
+        machine.execute_instr(&setup_catch(1)).unwrap();
+        let throw = Function::new(vec!["x"], program(vec![Instr::Throw { value: "x" }]));
+        machine.define_function("throw", throw);
+        let answer = machine.call("throw", vec![int(1)]);
+        assert_eq!(Err(KlvmError::Uncaught(int(1))), answer);
+        assert_eq!(1, machine.handlers.len());
+    }
+
+    #[test]
+    fn test_closure() {
+        // base = 10; add_base = fn (x) { return x + base }; base = 100; return add_base(5)
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(10),
+                target: "base",
+            },
+            Instr::CreateClosure {
+                params: vec!["x".into()],
+                body_start: 7,
+                capture_names: vec!["base".into()],
+                target: "add_base",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(100),
+                target: "base",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(5),
+                target: "five",
+            },
+            Instr::CallClosure {
+                closure: "add_base",
+                args: vec!["five"],
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+            Instr::Return { value: "base" },
+            Instr::AddInt32 {
+                left: "x",
+                right: "base",
+                target: "s",
+            },
+            Instr::Return { value: "s" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        // The closure captured base before it was changed
+        assert_eq!(Ok(int(15)), machine.execute());
+    }
+
+    #[test]
+    fn test_closure_outlives_frame() {
+        // make_adder(n) = fn (x) { return x + n }
+        let make_adder = Function::new(
+            vec!["n"],
+            program(vec![
+                Instr::CreateClosure {
+                    params: vec!["x".into()],
+                    body_start: 2,
+                    capture_names: vec!["n".into()],
+                    target: "adder",
+                },
+                Instr::Return { value: "adder" },
+                Instr::AddInt32 {
+                    left: "x",
+                    right: "n",
+                    target: "s",
+                },
+                Instr::Return { value: "s" },
+            ]),
+        );
+        let instrs = vec![
+            Instr::LoadConst {
+                value: Scalar::Int32(3),
+                target: "three",
+            },
+            Instr::LoadConst {
+                value: Scalar::Int32(4),
+                target: "four",
+            },
+            Instr::Call {
+                func: "make_adder",
+                args: vec!["three"],
+                target: "add3",
+            },
+            Instr::CallClosure {
+                closure: "add3",
+                args: vec!["four"],
+                target: "r",
+            },
+            Instr::Return { value: "r" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        machine.define_function("make_adder", make_adder);
+        assert_eq!(Ok(int(7)), machine.execute());
+        let Some(Value::Closure(closure)) = machine.lookup("add3") else {
+            panic!("add3 is not a closure");
+        };
+        assert_eq!("make_adder", closure.func_name());
+        assert_eq!(Some(&int(3)), closure.env().lookup("n"));
+    }
+
+    #[test]
+    fn test_closure_errors() {
+        let mut machine = Machine::new(Program::default());
+        let create = Instr::CreateClosure {
+            params: vec!["x".into()],
+            body_start: 0,
+            capture_names: vec!["y".into()],
+            target: "c",
+        };
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("y".to_string())),
+            machine.execute_instr(&create)
+        );
+        machine.bind("y", Value::Null);
+        machine.execute_instr(&create).unwrap();
+        let call = |closure| Instr::CallClosure {
+            closure,
+            args: vec![],
+            target: "r",
+        };
+        assert_eq!(
+            Err(KlvmError::ArityMismatch {
+                expected: 1,
+                found: 0
+            }),
+            machine.execute_instr(&call("c"))
+        );
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "closure",
+                found: "null"
+            }),
+            machine.execute_instr(&call("y"))
+        );
+    }
+
+    #[test]
+    fn test_create_list() {
+        let mut machine = Machine::new(Program::default());
+        machine.bind("a", int(1));
+        machine.bind("b", int(2));
+        let create = |elements| Instr::CreateList {
+            elements,
+            target: "l",
+        };
+        machine.execute_instr(&create(vec!["a", "b"])).unwrap();
+        let Some(Value::List(list)) = machine.lookup("l") else {
+            panic!("l is not a list");
+        };
+        assert_eq!("[1, 2]", list.to_string());
+        // Each instruction creates a new list
+        machine.execute_instr(&create(vec![])).unwrap();
+        assert_eq!(Some(&Value::List(List::new())), machine.lookup("l"));
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("c".to_string())),
+            machine.execute_instr(&create(vec!["c"]))
+        );
+    }
+
+    #[test]
+    fn test_create_tuple() {
+        let mut machine = Machine::new(Program::default());
+        machine.bind("a", int(1));
+        machine.bind("b", Value::Bool(true));
+        let create = |elements| Instr::CreateTuple {
+            elements,
+            target: "t",
+        };
+        machine.execute_instr(&create(vec!["a", "b", "a"])).unwrap();
+        let expected = Tuple::new(vec![int(1), Value::Bool(true), int(1)]);
+        assert_eq!(Some(&Value::Tuple(expected)), machine.lookup("t"));
+        machine.execute_instr(&create(vec![])).unwrap();
+        assert_eq!(Some(&Value::Tuple(Tuple::new(vec![]))), machine.lookup("t"));
+        assert_eq!(
+            Err(KlvmError::UndefinedVariable("c".to_string())),
+            machine.execute_instr(&create(vec!["a", "c"]))
+        );
+    }
+
+    #[test]
+    fn test_foreign_closure() {
+        let make = || {
+            let body = vec![
+                Instr::CreateClosure {
+                    params: vec![],
+                    body_start: 2,
+                    capture_names: vec![],
+                    target: "c",
+                },
+                Instr::Return { value: "c" },
+                Instr::Return { value: "c" },
+            ];
+            Function::new(vec![], program(body))
+        };
+        let call = Instr::CallClosure {
+            closure: "c",
+            args: vec![],
+            target: "r",
+        };
+        let mut machine = Machine::new(Program::default());
+        machine.define_function("make", make());
+        let closure = machine.call("make", vec![]).unwrap();
+        // Another machine defines a function of the same name with other code
+        let mut other = Machine::new(Program::default());
+        other.define_function("make", make());
+        other.bind("c", closure.clone());
+        let foreign = Err(KlvmError::ForeignClosure("make".to_string()));
+        assert_eq!(foreign, other.execute_instr(&call));
+        // Replacing the function also leaves the closure without its code
+        machine.bind("c", closure);
+        machine.define_function("make", make());
+        assert_eq!(foreign, machine.execute_instr(&call));
+    }
+
+    #[test]
+    fn test_actor_requests() {
+        let instrs = vec![
+            Instr::SpawnActor {
+                actor_def: "Counter".into(),
+                args: vec!["n"],
+                target: "counter",
+            },
+            Instr::TellActor {
+                actor_ref: "counter",
+                message: "n",
+            },
+            Instr::AskActor {
+                actor_ref: "counter",
+                message: "n",
+                reply_target: "count",
+            },
+            Instr::Receive { target: "msg" },
+            Instr::Reply { value: "msg" },
+        ];
+        let mut machine = Machine::new(program(instrs));
+        machine.bind("n", int(1));
+        assert_eq!(
+            Ok(StepResult::Request(ActorRequest::Spawn {
+                actor_def: "Counter".into(),
+                args: vec![int(1)],
+                target: "counter".into(),
+            })),
+            machine.step()
+        );
+        // The scheduler completes the spawn by binding the reference
+        let counter = ActorRef::new(7);
+        machine.bind("counter", Value::ActorRef(counter));
+        assert_eq!(
+            Ok(StepResult::Request(ActorRequest::Tell {
+                actor: counter,
+                message: int(1),
+            })),
+            machine.step()
+        );
+        assert_eq!(
+            Ok(StepResult::Request(ActorRequest::Ask {
+                actor: counter,
+                message: int(1),
+                reply_target: "count".into(),
+            })),
+            machine.step()
+        );
+        assert_eq!(
+            Ok(StepResult::Request(ActorRequest::Receive {
+                target: "msg".into(),
+            })),
+            machine.step()
+        );
+        machine.bind("msg", int(2));
+        assert_eq!(
+            Ok(StepResult::Request(ActorRequest::Reply { value: int(2) })),
+            machine.step()
+        );
+        assert_eq!(5, pc(&machine));
+    }
+
+    #[test]
+    fn test_actor_errors() {
+        let tell = Instr::TellActor {
+            actor_ref: "a",
+            message: "a",
+        };
+        let mut machine = Machine::new(program(vec![tell.clone()]));
+        machine.bind("a", int(1));
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "actor",
+                found: "i32"
+            }),
+            machine.execute_instr(&tell)
+        );
+        machine.bind("a", Value::ActorRef(ActorRef::new(1)));
+        let message = "Actor instruction executed outside a scheduler".to_string();
+        assert_eq!(Err(KlvmError::InternalError(message)), machine.execute());
+    }
+
+    /*
+     * Adds each message to `count` and replies with the new count.
+     */
+    fn counter_def() -> ActorDef<'static> {
+        let body = vec![
+            Instr::Receive { target: "n" },
+            Instr::AddInt32 {
+                left: "count",
+                right: "n",
+                target: "count",
+            },
+            Instr::Reply { value: "count" },
+            Instr::Jump { offset: -3 },
+        ];
+        ActorDef::new(vec!["count"], program(body))
+    }
+
+    #[test]
+    fn test_scheduler() {
+        for quantum in [1, 2, DEFAULT_QUANTUM] {
+            let instrs = vec![
+                Instr::LoadConst {
+                    value: Scalar::Int32(10),
+                    target: "start",
+                },
+                Instr::SpawnActor {
+                    actor_def: "Counter".into(),
+                    args: vec!["start"],
+                    target: "counter",
+                },
+                Instr::LoadConst {
+                    value: Scalar::Int32(5),
+                    target: "five",
+                },
+                // The reply to a tell is dropped
+                Instr::TellActor {
+                    actor_ref: "counter",
+                    message: "five",
+                },
+                Instr::AskActor {
+                    actor_ref: "counter",
+                    message: "five",
+                    reply_target: "total",
+                },
+                Instr::Receive { target: "done" },
+            ];
+            let mut scheduler = Scheduler::new();
+            scheduler.set_quantum(quantum);
+            scheduler.define_actor("Counter", counter_def());
+            let main = scheduler
+                .spawn(ActorDef::new(vec![], program(instrs)), vec![])
+                .unwrap();
+            assert_eq!(Ok(()), scheduler.run_until_idle());
+            assert_eq!(Some(&int(20)), scheduler.lookup(main, "total"));
+            let Some(&Value::ActorRef(counter)) = scheduler.lookup(main, "counter") else {
+                panic!("counter is not an actor");
+            };
+            assert_eq!(Some(&int(20)), scheduler.lookup(counter, "count"));
+            // Both actors are waiting for a message
+            scheduler.tell(main, Value::Null);
+            assert_eq!(Ok(()), scheduler.run_until_idle());
+            assert!(!scheduler.is_alive(main));
+            assert!(scheduler.is_alive(counter));
+        }
+    }
+
+    #[test]
+    fn test_scheduler_mailbox() {
+        let mut scheduler = Scheduler::default();
+        let counter = scheduler.spawn(counter_def(), vec![int(0)]).unwrap();
+        // Messages sent before the actor runs wait in its mailbox
+        scheduler.tell(counter, int(1));
+        scheduler.tell(counter, int(2));
+        assert_eq!(Ok(()), scheduler.run_until_idle());
+        assert_eq!(Some(&int(3)), scheduler.lookup(counter, "count"));
+        // A message wakes the actor waiting in Receive
+        scheduler.tell(counter, int(4));
+        assert_eq!(Some(&int(4)), scheduler.lookup(counter, "n"));
+        assert_eq!(Ok(()), scheduler.run_until_idle());
+        assert_eq!(Some(&int(7)), scheduler.lookup(counter, "count"));
+    }
+
+    #[test]
+    fn test_scheduler_errors() {
+        let mut scheduler = Scheduler::new();
+        assert_eq!(
+            Err(KlvmError::ArityMismatch {
+                expected: 1,
+                found: 0
+            }),
+            scheduler.spawn(counter_def(), vec![])
+        );
+        let spawn = Instr::SpawnActor {
+            actor_def: "Missing".into(),
+            args: vec![],
+            target: "a",
+        };
+        let main = scheduler
+            .spawn(ActorDef::new(vec![], program(vec![spawn])), vec![])
+            .unwrap();
+        assert_eq!(
+            Err(panic(
+                main,
+                KlvmError::UndefinedActor("Missing".to_string())
+            )),
+            scheduler.run_until_idle()
+        );
+        // The failed actor is terminated, and messages to it are dropped
+        assert!(!scheduler.is_alive(main));
+        scheduler.tell(main, Value::Null);
+        assert_eq!(Ok(()), scheduler.run_until_idle());
+    }
+
+    fn panic(actor: ActorRef, error: KlvmError) -> KlvmError {
+        KlvmError::ActorPanic {
+            actor,
+            error: Box::new(error),
+        }
+    }
+
+    /*
+     * Asks `receiver` for `n` and keeps the reply in `r`.
+     */
+    fn asker_def() -> ActorDef<'static> {
+        let body = vec![
+            Instr::AskActor {
+                actor_ref: "receiver",
+                message: "n",
+                reply_target: "r",
+            },
+            Instr::Receive { target: "done" },
+        ];
+        ActorDef::new(vec!["receiver", "n"], program(body))
+    }
+
+    #[test]
+    fn test_scheduler_zero_quantum() {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_quantum(0);
+        assert_eq!(1, scheduler.quantum());
+        let counter = scheduler.spawn(counter_def(), vec![int(0)]).unwrap();
+        scheduler.tell(counter, int(2));
+        assert_eq!(Ok(()), scheduler.run_until_idle());
+        assert_eq!(Some(&int(2)), scheduler.lookup(counter, "count"));
+    }
+
+    #[test]
+    fn test_scheduler_no_reply() {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_quantum(1);
+        // The receiver terminates with the ask in its mailbox
+        let body = vec![Instr::LoadConst {
+            value: Scalar::Null,
+            target: "x",
+        }];
+        let quitter = scheduler
+            .spawn(ActorDef::new(vec![], program(body)), vec![])
+            .unwrap();
+        let asker = scheduler
+            .spawn(asker_def(), vec![Value::ActorRef(quitter), int(1)])
+            .unwrap();
+        let no_reply = panic(asker, KlvmError::NoReply(quitter));
+        assert_eq!(Err(no_reply), scheduler.run_until_idle());
+        assert!(!scheduler.is_alive(asker));
+        // The receiver has already terminated
+        let asker = scheduler
+            .spawn(asker_def(), vec![Value::ActorRef(quitter), int(1)])
+            .unwrap();
+        let no_reply = panic(asker, KlvmError::NoReply(quitter));
+        assert_eq!(Err(no_reply), scheduler.run_until_idle());
+        assert!(!scheduler.is_alive(asker));
+        // The receiver terminates after receiving the ask
+        let body = vec![Instr::Receive { target: "m" }];
+        let receiver = scheduler
+            .spawn(ActorDef::new(vec![], program(body)), vec![])
+            .unwrap();
+        let asker = scheduler
+            .spawn(asker_def(), vec![Value::ActorRef(receiver), int(1)])
+            .unwrap();
+        let no_reply = panic(asker, KlvmError::NoReply(receiver));
+        assert_eq!(Err(no_reply), scheduler.run_until_idle());
+        assert_eq!(Ok(()), scheduler.run_until_idle());
+    }
+
+    #[test]
+    fn test_scheduler_nested_asks() {
+        // Receives two asks before replying to either
+        let body = vec![
+            Instr::Receive { target: "x" },
+            Instr::Receive { target: "y" },
+            Instr::Reply { value: "y" },
+            Instr::Reply { value: "x" },
+            Instr::Jump { offset: -4 },
+        ];
+        let mut scheduler = Scheduler::new();
+        let pair = scheduler
+            .spawn(ActorDef::new(vec![], program(body)), vec![])
+            .unwrap();
+        let a = scheduler
+            .spawn(asker_def(), vec![Value::ActorRef(pair), int(1)])
+            .unwrap();
+        let b = scheduler
+            .spawn(asker_def(), vec![Value::ActorRef(pair), int(2)])
+            .unwrap();
+        assert_eq!(Ok(()), scheduler.run_until_idle());
+        assert_eq!(Some(&int(1)), scheduler.lookup(a, "r"));
+        assert_eq!(Some(&int(2)), scheduler.lookup(b, "r"));
+    }
+}
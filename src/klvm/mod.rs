@@ -5,6 +5,10 @@
  * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
  */
 
+pub mod actor;
+pub mod bits;
+pub mod env;
+pub mod error;
 pub mod instr;
 pub mod machine;
 pub mod value;
@@ -5,38 +5,2340 @@
  * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
  */
 
+use crate::klvm::actor::ActorRef;
+use crate::klvm::env::Env;
+use crate::klvm::error::KlvmError;
+use crate::klvm::machine::NativeFunction;
+use crate::lang::lexer::{Token, TokenType};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[cfg(feature = "serde")]
+mod serialize;
+
+#[derive(Clone, Debug)]
 pub enum Scalar {
     Bool(bool),
     Char(char),
+    Dec(Decimal),
+    Flt32(f32),
+    Flt64(f64),
+    Int32(i32),
+    Int64(i64),
+    Null,
+    // Box<str> rather than String, because a scalar string never grows and need not carry a
+    // capacity
+    Str(Box<str>),
+}
+
+impl Scalar {
+    /// Convert a `Char` to an `Int32` holding its Unicode code point.
+    pub fn char_to_int(&self) -> Result<Scalar, ConversionError> {
+        match self {
+            Scalar::Char(c) => Ok(Scalar::Int32(*c as i32)),
+            _ => Err(ConversionError::NotAChar),
+        }
+    }
+
+    /// Convert an `Int32` code point to a `Char`. Surrogates and values outside the Unicode range
+    /// are rejected.
+    pub fn int_to_char(&self) -> Result<Scalar, ConversionError> {
+        match self {
+            Scalar::Int32(i) => u32::try_from(*i)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Scalar::Char)
+                .ok_or(ConversionError::InvalidCodePoint(*i)),
+            _ => Err(ConversionError::NotAnInt32),
+        }
+    }
+
+    /// Convert a `Dec` token, such as `3.14m`, to a `Dec` scalar with the scale as written.
+    pub fn from_dec_token(token: &Token) -> Result<Scalar, ConversionError> {
+        if token.token_type != TokenType::Dec {
+            return Err(ConversionError::InvalidDecimal);
+        }
+        token.value.parse().map(Scalar::Dec)
+    }
+
+    /// Compare two scalars of the same type. Scalars of different types are not comparable and
+    /// return `None`, as does any comparison with a NaN other than itself. Machine instructions
+    /// call this directly to evaluate `<`, `<=`, `>`, and `>=`.
+    pub fn cmp_same_type(a: &Scalar, b: &Scalar) -> Option<Ordering> {
+        match (a, b) {
+            (Scalar::Bool(a), Scalar::Bool(b)) => Some(a.cmp(b)),
+            (Scalar::Char(a), Scalar::Char(b)) => Some(a.cmp(b)),
+            (Scalar::Dec(a), Scalar::Dec(b)) => Some(a.cmp(b)),
+            (Scalar::Flt32(a), Scalar::Flt32(b)) => cmp_flt(*a as f64, *b as f64),
+            (Scalar::Flt64(a), Scalar::Flt64(b)) => cmp_flt(*a, *b),
+            (Scalar::Int32(a), Scalar::Int32(b)) => Some(a.cmp(b)),
+            (Scalar::Int64(a), Scalar::Int64(b)) => Some(a.cmp(b)),
+            (Scalar::Null, Scalar::Null) => Some(Ordering::Equal),
+            (Scalar::Str(a), Scalar::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+
+    /// Approximate number of bytes used by the scalar payload.
+    pub fn size_hint(&self) -> usize {
+        match self {
+            Scalar::Bool(_) | Scalar::Char(_) | Scalar::Flt32(_) | Scalar::Int32(_) => 4,
+            Scalar::Flt64(_) | Scalar::Int64(_) => 8,
+            Scalar::Dec(_) => 16,
+            Scalar::Null => 0,
+            Scalar::Str(v) => v.len(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConversionError {
+    InvalidCodePoint(i32),
+    InvalidDecimal,
+    NotAChar,
+    NotAnInt32,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::InvalidCodePoint(i) => {
+                write!(f, "{:#x} is not a valid Unicode code point", i)
+            }
+            ConversionError::InvalidDecimal => write!(f, "Value is not a valid decimal"),
+            ConversionError::NotAChar => write!(f, "Value is not a Char"),
+            ConversionError::NotAnInt32 => write!(f, "Value is not an Int32"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Scalars of different types are never equal, so `Bool(true) != Int32(1)`. Floats are equal when
+/// their bits are equal. That makes NaN equal to itself, which the VM needs for `Eq` and for
+/// hashing, and it makes `0.0` and `-0.0` unequal. Decimals compare by value, so `1.5m == 1.50m`.
+impl PartialEq for Scalar {
+    fn eq(&self, other: &Scalar) -> bool {
+        match (self, other) {
+            (Scalar::Bool(a), Scalar::Bool(b)) => a == b,
+            (Scalar::Char(a), Scalar::Char(b)) => a == b,
+            (Scalar::Dec(a), Scalar::Dec(b)) => a == b,
+            (Scalar::Flt32(a), Scalar::Flt32(b)) => a.to_bits() == b.to_bits(),
+            (Scalar::Flt64(a), Scalar::Flt64(b)) => a.to_bits() == b.to_bits(),
+            (Scalar::Int32(a), Scalar::Int32(b)) => a == b,
+            (Scalar::Int64(a), Scalar::Int64(b)) => a == b,
+            (Scalar::Null, Scalar::Null) => true,
+            (Scalar::Str(a), Scalar::Str(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Scalar {}
+
+/// Consistent with `PartialEq`: floats hash their bits and decimals hash their normalized form, so
+/// equal scalars always have equal hashes.
+impl Hash for Scalar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Scalar::Bool(v) => v.hash(state),
+            Scalar::Char(v) => v.hash(state),
+            Scalar::Dec(v) => v.hash(state),
+            Scalar::Flt32(v) => v.to_bits().hash(state),
+            Scalar::Flt64(v) => v.to_bits().hash(state),
+            Scalar::Int32(v) => v.hash(state),
+            Scalar::Int64(v) => v.hash(state),
+            Scalar::Null => {}
+            Scalar::Str(v) => v.hash(state),
+        }
+    }
+}
+
+/// Same as [`Scalar::cmp_same_type`]. `Scalar` is not `Ord` because floats and values of different
+/// types are not comparable.
+impl PartialOrd for Scalar {
+    fn partial_cmp(&self, other: &Scalar) -> Option<Ordering> {
+        Scalar::cmp_same_type(self, other)
+    }
+}
+
+/*
+ * Order floats consistently with `Scalar` equality: equal bits are `Equal`, a NaN is otherwise
+ * unordered, and `-0.0` sorts before `0.0` because the two are not equal.
+ */
+fn cmp_flt(a: f64, b: f64) -> Option<Ordering> {
+    if a.to_bits() == b.to_bits() {
+        Some(Ordering::Equal)
+    } else if a.is_nan() || b.is_nan() {
+        None
+    } else {
+        Some(a.total_cmp(&b))
+    }
+}
+
+/// Format the scalar as a Torq literal, so that lexing the text yields one token that denotes the
+/// same value. Negative numbers lex as "-" followed by the number. Non-finite floats have no
+/// literal form and are formatted as Rust formats them.
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Bool(v) => write!(f, "{}", v),
+            Scalar::Char(v) => fmt_char_literal(f, *v),
+            Scalar::Dec(v) => write!(f, "{}m", v),
+            Scalar::Flt32(v) if v.is_finite() => write!(f, "{}f", format_flt(*v)),
+            Scalar::Flt32(v) => write!(f, "{}", format_flt(*v)),
+            Scalar::Flt64(v) => write!(f, "{}", format_flt(*v)),
+            Scalar::Int32(v) => write!(f, "{}", v),
+            Scalar::Int64(v) => write!(f, "{}L", v),
+            Scalar::Null => write!(f, "null"),
+            Scalar::Str(v) => fmt_str_literal(f, v),
+        }
+    }
+}
+
+fn fmt_char_literal(f: &mut fmt::Formatter<'_>, c: char) -> fmt::Result {
+    match c {
+        '\n' => write!(f, "$'\\n'"),
+        '\t' => write!(f, "$'\\t'"),
+        '\\' | '\'' => write!(f, "$'\\{}'", c),
+        _ => write!(f, "$'{}'", c),
+    }
+}
+
+fn fmt_str_literal(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            '\\' | '"' => write!(f, "\\{}", c)?,
+            _ => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Fixed-point decimal number equal to `mantissa * 10^-scale`. For example, `3.14m` has mantissa
+/// 314 and scale 2. The scale is kept as written, so `1.50m` displays as `1.50` and not `1.5`.
+///
+/// The `checked_*` operations return `None` when the result does not fit, and the `saturating_*`
+/// operations clamp it to the bounds of the mantissa, so arithmetic never panics.
+// Packed to 8-byte alignment so that the 16-byte aligned `i128` does not grow `Scalar` from 32 to
+// 48 bytes. Fields are only ever read by value.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed(8))]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u8,
+}
+
+impl Decimal {
+    /// Largest scale. An `i128` mantissa holds 38 decimal digits.
+    pub const MAX_SCALE: u8 = 38;
+
+    /// Create `mantissa * 10^-scale`. A scale above `MAX_SCALE` is reduced to it, and the extra
+    /// fractional digits are rounded half up, away from zero.
+    pub fn new(mantissa: i128, scale: u8) -> Decimal {
+        if scale > Self::MAX_SCALE {
+            let excess = (scale - Self::MAX_SCALE) as u32;
+            // An i128 has at most 39 digits, so dropping more than 39 of them leaves zero, and
+            // dropping exactly 39 leaves less than a half
+            let mantissa = match 10i128.checked_pow(excess) {
+                Some(divisor) => {
+                    let remainder = (mantissa % divisor).unsigned_abs();
+                    let rounding = remainder >= divisor.unsigned_abs() - remainder;
+                    mantissa / divisor + if rounding { mantissa.signum() } else { 0 }
+                }
+                None => 0,
+            };
+            return Decimal {
+                mantissa,
+                scale: Self::MAX_SCALE,
+            };
+        }
+        Decimal { mantissa, scale }
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .checked_rescaled_mantissa(scale)?
+            .checked_add(other.checked_rescaled_mantissa(scale)?)?;
+        Some(Decimal { mantissa, scale })
+    }
+
+    /// Divide exactly when the quotient has at most `MAX_SCALE` fractional digits and fits in the
+    /// mantissa. Otherwise the quotient is rounded half up, away from zero, to as many digits as
+    /// fit. Returns `None` for a zero divisor or an integer part that does not fit.
+    pub fn checked_div(self, other: Decimal) -> Option<Decimal> {
+        let divisor = other.mantissa.unsigned_abs();
+        if divisor == 0 {
+            return None;
+        }
+        let negative = (self.mantissa < 0) != (other.mantissa < 0);
+        // The magnitude of a negative mantissa can be one more than i128::MAX
+        let limit = if negative {
+            i128::MIN.unsigned_abs()
+        } else {
+            i128::MAX as u128
+        };
+        let dividend = self.mantissa.unsigned_abs();
+        let (mut quotient, mut remainder) = (dividend / divisor, dividend % divisor);
+        let mut scale = self.scale as i32 - other.scale as i32;
+        // Long division, one digit at a time. A negative scale needs digits to be exact, and a
+        // remainder needs them while there is room for another fractional digit.
+        while scale < 0 || (remainder != 0 && scale < Self::MAX_SCALE as i32) {
+            let shifted = remainder.checked_mul(10);
+            let next = shifted.and_then(|shifted| {
+                quotient
+                    .checked_mul(10)?
+                    .checked_add(shifted / divisor)
+                    .filter(|q| *q <= limit)
+            });
+            match (next, shifted) {
+                (Some(next), Some(shifted)) => {
+                    quotient = next;
+                    remainder = shifted % divisor;
+                    scale += 1;
+                }
+                _ if scale < 0 => return None,
+                _ => break,
+            }
+        }
+        if remainder != 0 && remainder >= divisor - remainder {
+            quotient += 1;
+        }
+        let mantissa = if negative {
+            0i128.checked_sub_unsigned(quotient)?
+        } else {
+            i128::try_from(quotient).ok()?
+        };
+        Some(Decimal {
+            mantissa,
+            scale: scale as u8,
+        })
+    }
+
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale as u32 + other.scale as u32;
+        let mantissa = self.mantissa.checked_mul(other.mantissa)?;
+        Some(Decimal::new(mantissa, scale.min(u8::MAX as u32) as u8))
+    }
+
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .checked_rescaled_mantissa(scale)?
+            .checked_sub(other.checked_rescaled_mantissa(scale)?)?;
+        Some(Decimal { mantissa, scale })
+    }
+
+    pub fn saturating_add(self, other: Decimal) -> Decimal {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .rescaled_mantissa(scale)
+            .saturating_add(other.rescaled_mantissa(scale));
+        Decimal { mantissa, scale }
+    }
+
+    pub fn saturating_mul(self, other: Decimal) -> Decimal {
+        let scale = self.scale as u32 + other.scale as u32;
+        match self.mantissa.checked_mul(other.mantissa) {
+            Some(mantissa) if scale > Self::MAX_SCALE as u32 => {
+                Decimal::new(mantissa, scale.min(u8::MAX as u32) as u8)
+            }
+            Some(mantissa) => Decimal {
+                mantissa,
+                scale: scale as u8,
+            },
+            None => {
+                let negative = (self.mantissa < 0) != (other.mantissa < 0);
+                Decimal {
+                    mantissa: if negative { i128::MIN } else { i128::MAX },
+                    scale: scale.min(Self::MAX_SCALE as u32) as u8,
+                }
+            }
+        }
+    }
+
+    pub fn saturating_sub(self, other: Decimal) -> Decimal {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .rescaled_mantissa(scale)
+            .saturating_sub(other.rescaled_mantissa(scale));
+        Decimal { mantissa, scale }
+    }
+
+    /*
+     * Post-condition: the result has the same value and no trailing fractional zeros, so equal
+     * decimals have equal mantissas and scales.
+     */
+    fn normalized(self) -> Decimal {
+        let (mut mantissa, mut scale) = (self.mantissa, self.scale);
+        while scale > 0 && mantissa % 10 == 0 {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        Decimal { mantissa, scale }
+    }
+
+    /*
+     * Pre-condition: `scale` is at least `self.scale` and at most `MAX_SCALE`.
+     */
+    fn checked_rescaled_mantissa(&self, scale: u8) -> Option<i128> {
+        self.mantissa
+            .checked_mul(10i128.pow((scale - self.scale) as u32))
+    }
+
+    /*
+     * Pre-condition: `scale` is at least `self.scale` and at most `MAX_SCALE`.
+     */
+    fn rescaled_mantissa(&self, scale: u8) -> i128 {
+        self.mantissa
+            .saturating_mul(10i128.pow((scale - self.scale) as u32))
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        let (a, b) = (self.normalized(), other.normalized());
+        let (a_mantissa, b_mantissa) = (a.mantissa, b.mantissa);
+        a_mantissa == b_mantissa && a.scale == b.scale
+    }
+}
+
+impl Eq for Decimal {}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let normalized = self.normalized();
+        let (mantissa, scale) = (normalized.mantissa, normalized.scale);
+        mantissa.hash(state);
+        scale.hash(state);
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.mantissa, other.mantissa);
+        match (
+            self.checked_rescaled_mantissa(scale),
+            other.checked_rescaled_mantissa(scale),
+        ) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            // A mantissa that overflows when rescaled is larger in magnitude than the other
+            (None, _) if a < 0 => Ordering::Less,
+            (None, _) => Ordering::Greater,
+            (_, None) if b < 0 => Ordering::Greater,
+            (_, None) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// Parse a decimal as written in Torq source, such as `3.14m`, `1_000.50M`, or `1.5e3m`. The
+/// suffix is optional.
+impl FromStr for Decimal {
+    type Err = ConversionError;
+
+    fn from_str(s: &str) -> Result<Decimal, ConversionError> {
+        let s = s.strip_suffix(['m', 'M']).unwrap_or(s);
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (number, exponent) = match s.find(['e', 'E']) {
+            Some(e) => (&s[..e], s[e + 1..].parse::<i32>().ok()),
+            None => (s, Some(0)),
+        };
+        let exponent = exponent.ok_or(ConversionError::InvalidDecimal)?;
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let digits: String = whole
+            .chars()
+            .chain(fraction.chars())
+            .filter(|c| *c != '_')
+            .collect();
+        if whole.is_empty() || digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ConversionError::InvalidDecimal);
+        }
+        let mut mantissa: i128 = digits
+            .parse()
+            .map_err(|_| ConversionError::InvalidDecimal)?;
+        if negative {
+            mantissa = -mantissa;
+        }
+        let scale = (fraction.chars().filter(|c| *c != '_').count() as i32)
+            .checked_sub(exponent)
+            .ok_or(ConversionError::InvalidDecimal)?;
+        if scale < 0 {
+            mantissa = 10i128
+                .checked_pow((-scale) as u32)
+                .and_then(|p| mantissa.checked_mul(p))
+                .ok_or(ConversionError::InvalidDecimal)?;
+            return Ok(Decimal { mantissa, scale: 0 });
+        }
+        if scale > Decimal::MAX_SCALE as i32 {
+            return Err(ConversionError::InvalidDecimal);
+        }
+        Ok(Decimal {
+            mantissa,
+            scale: scale as u8,
+        })
+    }
+}
+
+/*
+ * Format a float so that it always reads back as a float: the text includes a decimal point, and
+ * very large or very small magnitudes use scientific notation. Non-finite values are formatted as
+ * Rust formats them ("inf", "-inf", "NaN").
+ */
+fn format_flt<T>(value: T) -> String
+where
+    T: Copy + fmt::Display + fmt::LowerExp + Into<f64>,
+{
+    let v: f64 = value.into();
+    if !v.is_finite() {
+        return value.to_string();
+    }
+    let abs = v.abs();
+    let s = if abs != 0.0 && !(1e-6..1e16).contains(&abs) {
+        format!("{:e}", value)
+    } else {
+        value.to_string()
+    };
+    if s.contains('.') {
+        s
+    } else if let Some(e) = s.find('e') {
+        format!("{}.0{}", &s[..e], &s[e..])
+    } else {
+        s + ".0"
+    }
+}
+
+/// Options for [`format_for_display`], which formats scalars for people rather than for the lexer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatOptions {
+    pub thousands_sep: Option<char>,
+    pub decimal_sep: char,
+    /// Number of decimal places for floats. Integers ignore this.
+    pub precision: Option<usize>,
+    /// Use scientific notation for very large or very small float magnitudes, as `Display` does.
+    pub use_scientific: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            thousands_sep: None,
+            decimal_sep: '.',
+            precision: None,
+            use_scientific: true,
+        }
+    }
+}
+
+impl FormatOptions {
+    #[allow(non_snake_case)]
+    pub fn en_US() -> FormatOptions {
+        FormatOptions {
+            thousands_sep: Some(','),
+            ..FormatOptions::default()
+        }
+    }
+
+    #[allow(non_snake_case)]
+    pub fn de_DE() -> FormatOptions {
+        FormatOptions {
+            thousands_sep: Some('.'),
+            decimal_sep: ',',
+            ..FormatOptions::default()
+        }
+    }
+
+    pub fn precision(mut self, precision: usize) -> FormatOptions {
+        self.precision = Some(precision);
+        self
+    }
+}
+
+/// Format a scalar using the given separators and precision. With `FormatOptions::default()` the
+/// result is the same as `Display`.
+pub fn format_for_display(value: &Scalar, opts: &FormatOptions) -> String {
+    match value {
+        Scalar::Bool(_) | Scalar::Char(_) | Scalar::Null | Scalar::Str(_) => value.to_string(),
+        Scalar::Flt32(v) if v.is_finite() => format!("{}f", format_flt_with(*v, opts)),
+        Scalar::Flt32(v) => format_flt_with(*v, opts),
+        Scalar::Flt64(v) => format_flt_with(*v, opts),
+        Scalar::Dec(v) => format!("{}m", localize_number(&v.to_string(), opts)),
+        Scalar::Int32(v) => localize_number(&v.to_string(), opts),
+        Scalar::Int64(v) => format!("{}L", localize_number(&v.to_string(), opts)),
+    }
+}
+
+fn format_flt_with<T>(value: T, opts: &FormatOptions) -> String
+where
+    T: Copy + fmt::Display + fmt::LowerExp + Into<f64>,
+{
+    let v: f64 = value.into();
+    if !v.is_finite() {
+        return value.to_string();
+    }
+    let abs = v.abs();
+    let scientific = opts.use_scientific && abs != 0.0 && !(1e-6..1e16).contains(&abs);
+    let s = match (scientific, opts.precision) {
+        (true, Some(p)) => format!("{:.*e}", p, value),
+        (false, Some(p)) => format!("{:.*}", p, value),
+        (true, None) => format_flt(value),
+        (false, None) => {
+            let s = value.to_string();
+            if s.contains('.') {
+                s
+            } else {
+                s + ".0"
+            }
+        }
+    };
+    localize_number(&s, opts)
+}
+
+/*
+ * Pre-condition: `s` is a number formatted by Rust, an optional '-' followed by digits, an
+ * optional '.' fraction and an optional 'e' exponent.
+ */
+fn localize_number(s: &str, opts: &FormatOptions) -> String {
+    let (sign, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", s),
+    };
+    let (mantissa, exponent) = match unsigned.find('e') {
+        Some(e) => unsigned.split_at(e),
+        None => (unsigned, ""),
+    };
+    let (whole, fraction) = match mantissa.find('.') {
+        Some(d) => (&mantissa[..d], Some(&mantissa[d + 1..])),
+        None => (mantissa, None),
+    };
+    let mut answer = String::with_capacity(s.len() + whole.len() / 3 + 1);
+    answer.push_str(sign);
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            if let Some(sep) = opts.thousands_sep {
+                answer.push(sep);
+            }
+        }
+        answer.push(c);
+    }
+    if let Some(fraction) = fraction {
+        answer.push(opts.decimal_sep);
+        answer.push_str(fraction);
+    }
+    answer.push_str(exponent);
+    answer
+}
+
+/// Any value the machine computes with. The scalar variants hold the same payloads as `Scalar` and
+/// follow the same equality rules. The remaining variants are composites.
+// `Obj` is boxed because it is rarely used and larger than every other payload, which would grow
+// every value from 32 to 48 bytes.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Bool(bool),
+    Char(char),
+    Dec(Decimal),
     Flt32(f32),
     Flt64(f64),
     Int32(i32),
     Int64(i64),
+    Null,
+    Str(Box<str>),
+    ActorRef(ActorRef),
+    Closure(Arc<Closure>),
+    List(List),
+    Obj(Box<Obj>),
+    Rec(Rec),
+    Tuple(Tuple),
+}
+
+impl Value {
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Value::Bool(_))
+    }
+
+    /// True for `Flt32` and `Flt64`.
+    pub fn is_flt(&self) -> bool {
+        matches!(self, Value::Flt32(_) | Value::Flt64(_))
+    }
+
+    /// True for `Int32` and `Int64`.
+    pub fn is_int(&self) -> bool {
+        matches!(self, Value::Int32(_) | Value::Int64(_))
+    }
+
+    /// True for a `Flt32` or `Flt64` that is NaN, such as the result of `0.0 / 0.0`. Other types
+    /// are never NaN.
+    pub fn is_nan(&self) -> bool {
+        match self {
+            Value::Flt32(v) => v.is_nan(),
+            Value::Flt64(v) => v.is_nan(),
+            _ => false,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn is_str(&self) -> bool {
+        matches!(self, Value::Str(_))
+    }
+
+    /// Name of the value's type for error messages, such as "expected i32, got bool".
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::Char(_) => "char",
+            Value::Dec(_) => "dec",
+            Value::Flt32(_) => "f32",
+            Value::Flt64(_) => "f64",
+            Value::Int32(_) => "i32",
+            Value::Int64(_) => "i64",
+            Value::Null => "null",
+            Value::Str(_) => "str",
+            Value::ActorRef(_) => "actor",
+            Value::Closure(_) => "closure",
+            Value::List(_) => "list",
+            Value::Obj(_) => "obj",
+            Value::Rec(_) => "rec",
+            Value::Tuple(_) => "tuple",
+        }
+    }
+
+    /// Conservative estimate of the number of bytes used by the value's payload and contents.
+    pub fn size_hint(&self) -> usize {
+        match self {
+            Value::Bool(_) | Value::Char(_) | Value::Flt32(_) | Value::Int32(_) => 4,
+            Value::Flt64(_) | Value::Int64(_) => 8,
+            Value::Dec(_) => 16,
+            Value::Null => 0,
+            Value::Str(v) => v.len(),
+            Value::ActorRef(_) => 8,
+            Value::Closure(v) => v.size_hint(),
+            Value::List(v) => v.size_hint(),
+            Value::Obj(v) => v.size_hint(),
+            Value::Rec(v) => v.size_hint(),
+            Value::Tuple(v) => v.size_hint(),
+        }
+    }
+}
+
+impl From<Scalar> for Value {
+    fn from(scalar: Scalar) -> Value {
+        match scalar {
+            Scalar::Bool(v) => Value::Bool(v),
+            Scalar::Char(v) => Value::Char(v),
+            Scalar::Dec(v) => Value::Dec(v),
+            Scalar::Flt32(v) => Value::Flt32(v),
+            Scalar::Flt64(v) => Value::Flt64(v),
+            Scalar::Int32(v) => Value::Int32(v),
+            Scalar::Int64(v) => Value::Int64(v),
+            Scalar::Null => Value::Null,
+            Scalar::Str(v) => Value::Str(v),
+        }
+    }
+}
+
+/// Same rules as `Scalar` for the scalar variants. A closure is only equal to itself. Values of
+/// different variants are never equal.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Dec(a), Value::Dec(b)) => a == b,
+            (Value::Flt32(a), Value::Flt32(b)) => a.to_bits() == b.to_bits(),
+            (Value::Flt64(a), Value::Flt64(b)) => a.to_bits() == b.to_bits(),
+            (Value::Int32(a), Value::Int32(b)) => a == b,
+            (Value::Int64(a), Value::Int64(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::ActorRef(a), Value::ActorRef(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => Arc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Obj(a), Value::Obj(b)) => a == b,
+            (Value::Rec(a), Value::Rec(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Scalar variants are formatted as Torq literals, the same as `Scalar`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Char(v) => fmt_char_literal(f, *v),
+            Value::Dec(v) => write!(f, "{}m", v),
+            Value::Flt32(v) if v.is_finite() => write!(f, "{}f", format_flt(*v)),
+            Value::Flt32(v) => write!(f, "{}", format_flt(*v)),
+            Value::Flt64(v) => write!(f, "{}", format_flt(*v)),
+            Value::Int32(v) => write!(f, "{}", v),
+            Value::Int64(v) => write!(f, "{}L", v),
+            Value::Null => write!(f, "null"),
+            Value::Str(v) => fmt_str_literal(f, v),
+            Value::ActorRef(v) => write!(f, "{}", v),
+            Value::Closure(v) => write!(f, "{}", v),
+            Value::List(v) => write!(f, "{}", v),
+            Value::Obj(v) => write!(f, "{}", v),
+            Value::Rec(v) => write!(f, "{}", v),
+            Value::Tuple(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Record of named fields. Fields keep the order in which they were first set.
+#[derive(Clone, Debug)]
+pub struct Rec {
+    fields: Vec<(Box<str>, Value)>,
+}
+
+impl Rec {
+    pub fn new() -> Rec {
+        Rec { fields: vec![] }
+    }
+
+    /// Iterate the fields in the order they were first set.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.fields.iter().map(|(name, value)| (&**name, value))
+    }
+
+    /// Return a new record with only the fields for which `predicate` returns true, in the same
+    /// order.
+    pub fn filter_fields(&self, predicate: impl Fn(&str, &Value) -> bool) -> Rec {
+        let fields = self
+            .fields
+            .iter()
+            .filter(|(name, value)| predicate(name, value))
+            .cloned()
+            .collect();
+        Rec { fields }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Return a new record with each field renamed to `f(name)`. When two fields get the same
+    /// name, the value of the later one wins, at the position of the earlier one.
+    pub fn map_keys(&self, f: impl Fn(&str) -> String) -> Rec {
+        let mut answer = Rec::new();
+        for (name, value) in self.fields() {
+            answer.set(&f(name), value.clone());
+        }
+        answer
+    }
+
+    /// Return a new record with the same field names, in the same order, and each value replaced
+    /// by `f(name, value)`.
+    pub fn map_values(&self, f: impl Fn(&str, &Value) -> Value) -> Rec {
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), f(name, value)))
+            .collect();
+        Rec { fields }
+    }
+
+    /// Fold the fields into one value, in order, starting from `init`.
+    pub fn reduce_values<T>(self, init: T, f: impl Fn(T, &str, Value) -> T) -> T {
+        self.fields
+            .into_iter()
+            .fold(init, |acc, (name, value)| f(acc, &name, value))
+    }
+
+    /// Set a field, replacing its value in place if the field already exists.
+    pub fn set(&mut self, name: &str, value: Value) {
+        match self.fields.iter_mut().find(|(n, _)| &**n == name) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((name.into(), value)),
+        }
+    }
+
+    /// Conservative estimate of the number of bytes used by the record and its fields.
+    pub fn size_hint(&self) -> usize {
+        size_of::<Rec>()
+            + self
+                .fields
+                .iter()
+                .map(|(name, value)| {
+                    size_of::<(Box<str>, Value)>() + name.len() + value.size_hint()
+                })
+                .sum::<usize>()
+    }
+}
+
+impl Default for Rec {
+    fn default() -> Rec {
+        Rec::new()
+    }
+}
+
+/// Records are equal when they have the same fields with equal values, in any order.
+impl PartialEq for Rec {
+    fn eq(&self, other: &Rec) -> bool {
+        self.len() == other.len()
+            && self
+                .fields()
+                .all(|(name, value)| other.get(name) == Some(value))
+    }
+}
+
+impl fmt::Display for Rec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (name, value)) in self.fields().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// Immutable sequence of positional values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuple(Vec<Value>);
+
+impl Tuple {
+    pub fn new(elements: Vec<Value>) -> Tuple {
+        Tuple(elements)
+    }
+
+    /// Return the concatenation of the tuples returned by `f` for each element, in order.
+    pub fn flat_map(&self, f: impl Fn(&Value) -> Tuple) -> Tuple {
+        Tuple(self.iter().flat_map(|v| f(v).0).collect())
+    }
+
+    /// Return the element at `index`, or `None` if `index` is out of bounds.
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.0.get(index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Conservative estimate of the number of bytes used by the tuple and its elements.
+    pub fn size_hint(&self) -> usize {
+        size_of::<Tuple>()
+            + self
+                .iter()
+                .map(|v| size_of::<Value>() + v.size_hint())
+                .sum::<usize>()
+    }
+
+    /// Split a tuple of pairs, each a two-element tuple, into a tuple of first elements and a
+    /// tuple of second elements. The inverse of `zip`.
+    pub fn unzip(pairs: &Tuple) -> Result<(Tuple, Tuple), UnzipError> {
+        let mut firsts = Vec::with_capacity(pairs.len());
+        let mut seconds = Vec::with_capacity(pairs.len());
+        for (index, pair) in pairs.iter().enumerate() {
+            match pair {
+                Value::Tuple(Tuple(pair)) if pair.len() == 2 => {
+                    firsts.push(pair[0].clone());
+                    seconds.push(pair[1].clone());
+                }
+                _ => return Err(UnzipError::ElementNotAPair { index }),
+            }
+        }
+        Ok((Tuple(firsts), Tuple(seconds)))
+    }
+
+    /// Pair the elements of `a` and `b` by position. The result has the length of the shorter
+    /// tuple, and each element is a two-element tuple.
+    pub fn zip(a: &Tuple, b: &Tuple) -> Tuple {
+        let pairs = a.iter().zip(b.iter());
+        Tuple(
+            pairs
+                .map(|(x, y)| Value::Tuple(Tuple(vec![x.clone(), y.clone()])))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UnzipError {
+    ElementNotAPair { index: usize },
+}
+
+impl fmt::Display for UnzipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnzipError::ElementNotAPair { index } => {
+                write!(f, "Element {} is not a two-element tuple", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnzipError {}
+
+/// `Tuple::zip` and `Tuple::unzip` as KLVM natives, which every machine defines. `$tuple_zip`
+/// takes two tuples, and `$tuple_unzip` takes a tuple of pairs and returns a pair of tuples.
+/// `flat_map` has no native, because a native cannot call a closure.
+pub const TUPLE_NATIVES: [(&str, NativeFunction); 2] = [
+    ("$tuple_unzip", tuple_unzip_native),
+    ("$tuple_zip", tuple_zip_native),
+];
+
+fn tuple_unzip_native(args: &[Value]) -> Result<Value, KlvmError> {
+    let [pairs] = args else {
+        return Err(KlvmError::ArityMismatch {
+            expected: 1,
+            found: args.len(),
+        });
+    };
+    let (firsts, seconds) = Tuple::unzip(tuple_arg(pairs)?)?;
+    Ok(Value::Tuple(Tuple(vec![
+        Value::Tuple(firsts),
+        Value::Tuple(seconds),
+    ])))
+}
+
+fn tuple_zip_native(args: &[Value]) -> Result<Value, KlvmError> {
+    let [a, b] = args else {
+        return Err(KlvmError::ArityMismatch {
+            expected: 2,
+            found: args.len(),
+        });
+    };
+    Ok(Value::Tuple(Tuple::zip(tuple_arg(a)?, tuple_arg(b)?)))
+}
+
+fn tuple_arg(value: &Value) -> Result<&Tuple, KlvmError> {
+    match value {
+        Value::Tuple(tuple) => Ok(tuple),
+        other => Err(KlvmError::TypeMismatch {
+            expected: "tuple",
+            found: other.type_name(),
+        }),
+    }
+}
+
+impl fmt::Display for Tuple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, value) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Growable sequence of values. Indexing past the end fails with `KlvmError::IndexOutOfBounds`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct List(Vec<Value>);
+
+impl List {
+    pub fn new() -> List {
+        List(vec![])
+    }
+
+    pub fn get(&self, index: usize) -> Result<&Value, KlvmError> {
+        self.0.get(index).ok_or(KlvmError::IndexOutOfBounds {
+            index,
+            len: self.len(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        self.0.pop()
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.0.push(value);
+    }
+
+    pub fn set(&mut self, index: usize, value: Value) -> Result<(), KlvmError> {
+        let len = self.len();
+        match self.0.get_mut(index) {
+            Some(v) => {
+                *v = value;
+                Ok(())
+            }
+            None => Err(KlvmError::IndexOutOfBounds { index, len }),
+        }
+    }
+
+    /// Conservative estimate of the number of bytes used by the list and its elements.
+    pub fn size_hint(&self) -> usize {
+        size_of::<List>()
+            + self
+                .iter()
+                .map(|v| size_of::<Value>() + v.size_hint())
+                .sum::<usize>()
+    }
+
+    /// Copy the elements in `start..end` into a new list. The range must lie within the list, and
+    /// `start` must not be greater than `end`.
+    pub fn slice(&self, start: usize, end: usize) -> Result<List, KlvmError> {
+        if end > self.len() {
+            return Err(KlvmError::IndexOutOfBounds {
+                index: end,
+                len: self.len(),
+            });
+        }
+        if start > end {
+            return Err(KlvmError::IndexOutOfBounds {
+                index: start,
+                len: self.len(),
+            });
+        }
+        Ok(List(self.0[start..end].to_vec()))
+    }
 }
 
-pub enum Comp {
-    Obj(ToBeDefined),
-    Rec(ToBeDefined),
-    Tuple(ToBeDefined),
+impl From<Vec<Value>> for List {
+    fn from(elements: Vec<Value>) -> List {
+        List(elements)
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, value) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", value)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Native code that implements a method. It receives the object's state and the message
+/// arguments.
+pub type NativeMethod = fn(&mut Rec, &[Value]) -> Result<Value, KlvmError>;
+
+/// Reference to the code that runs when an object handles a message.
+#[derive(Clone, Copy, Debug)]
+pub struct FuncRef(pub NativeMethod);
+
+/// Mutable object with its own state and a table of methods. Unlike a `Rec`, which is only data,
+/// an object responds to messages by running the method of the same name.
+///
+/// A clone has its own deep copy of the state. Its method table refers to the same code.
+#[derive(Clone, Debug)]
+pub struct Obj {
+    state: Rec,
+    methods: Vec<(Box<str>, FuncRef)>,
+}
+
+impl Obj {
+    pub fn new(state: Rec) -> Obj {
+        Obj {
+            state,
+            methods: vec![],
+        }
+    }
+
+    /// Add a method, replacing any method of the same name.
+    pub fn define_method(&mut self, name: &str, func: FuncRef) {
+        match self.methods.iter_mut().find(|(n, _)| &**n == name) {
+            Some((_, f)) => *f = func,
+            None => self.methods.push((name.into(), func)),
+        }
+    }
+
+    pub fn method(&self, name: &str) -> Option<FuncRef> {
+        self.methods
+            .iter()
+            .find(|(n, _)| &**n == name)
+            .map(|(_, func)| *func)
+    }
+
+    /// Invoke the method named `message` with `args`. An object without such a method fails with
+    /// `KlvmError::UnhandledMessage`.
+    pub fn send(&mut self, message: &str, args: &[Value]) -> Result<Value, KlvmError> {
+        match self.method(message) {
+            Some(FuncRef(func)) => func(&mut self.state, args),
+            None => Err(KlvmError::UnhandledMessage(Value::Str(message.into()))),
+        }
+    }
+
+    /// Conservative estimate of the number of bytes used by the object, its state, and its method
+    /// table.
+    pub fn size_hint(&self) -> usize {
+        size_of::<Obj>() - size_of::<Rec>()
+            + self.state.size_hint()
+            + self
+                .methods
+                .iter()
+                .map(|(name, _)| size_of::<(Box<str>, FuncRef)>() + name.len())
+                .sum::<usize>()
+    }
+
+    pub fn state(&self) -> &Rec {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut Rec {
+        &mut self.state
+    }
+}
+
+/// Objects are equal when their states are equal and they handle the same messages. Method code is
+/// not compared.
+impl PartialEq for Obj {
+    fn eq(&self, other: &Obj) -> bool {
+        self.state == other.state
+            && self.methods.len() == other.methods.len()
+            && self
+                .methods
+                .iter()
+                .all(|(name, _)| other.method(name).is_some())
+    }
 }
 
-pub enum ScalarOrComp {
-    Scalar(Scalar),
-    Comp(Comp),
+impl fmt::Display for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<obj {}>", self.state)
+    }
 }
 
-pub struct ToBeDefined {
-    value: Vec<String>,
+/// Function value together with the bindings it captured when it was created. Its code is the part
+/// of the function `func_name` that starts at `start_pc`. A value cannot borrow from a program, so
+/// a closure names its code rather than holding it. The function's id, see `Function::id`,
+/// identifies the code, so that a machine whose function of that name differs can reject the
+/// closure.
+///
+/// A closure is immutable, and `Value::Closure` shares it on clone.
+#[derive(Clone, Debug)]
+pub struct Closure {
+    func_name: Box<str>,
+    func_id: u64,
+    start_pc: usize,
+    params: Vec<Box<str>>,
+    env: Env,
 }
 
-impl ToBeDefined {
-    pub fn new() -> ToBeDefined {
-        ToBeDefined { value: vec![] }
+impl Closure {
+    pub fn new(
+        func_name: &str,
+        func_id: u64,
+        start_pc: usize,
+        params: Vec<Box<str>>,
+        env: Env,
+    ) -> Closure {
+        Closure {
+            func_name: func_name.into(),
+            func_id,
+            start_pc,
+            params,
+            env,
+        }
+    }
+
+    /// The captured bindings.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    pub fn func_id(&self) -> u64 {
+        self.func_id
+    }
+
+    pub fn func_name(&self) -> &str {
+        &self.func_name
+    }
+
+    pub fn params(&self) -> &[Box<str>] {
+        &self.params
+    }
+
+    /// Conservative estimate of the number of bytes used by the closure and its captured bindings.
+    pub fn size_hint(&self) -> usize {
+        size_of::<Closure>()
+            + self.func_name.len()
+            + self
+                .params
+                .iter()
+                .map(|p| size_of::<Box<str>>() + p.len())
+                .sum::<usize>()
+            + self.env.size_hint()
+    }
+
+    pub fn start_pc(&self) -> usize {
+        self.start_pc
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<closure ({})>", self.params.join(", "))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lang::lexer::lex_all;
+    use crate::lang::token_value::unescape;
     use std::collections::HashMap;
+
+    #[test]
+    fn test_char_to_int() {
+        let v = Scalar::Char('A').char_to_int().unwrap();
+        assert!(matches!(v, Scalar::Int32(65)));
+        let v = Scalar::Char('🦀').char_to_int().unwrap();
+        assert!(matches!(v, Scalar::Int32(0x1F980)));
+        assert_eq!(
+            Some(ConversionError::NotAChar),
+            Scalar::Int32(65).char_to_int().err()
+        );
+    }
+
+    #[test]
+    fn test_value_size_hint() {
+        let empty = Value::Obj(Box::new(Obj::new(Rec::new()))).size_hint();
+        let mut obj = Obj::new(Rec::new());
+        obj.define_method("incr", FuncRef(incr));
+        assert!(Value::Obj(Box::new(obj)).size_hint() >= empty + 4);
+        let empty = Value::Tuple(Tuple::new(vec![])).size_hint();
+        let v = Value::Tuple(Tuple::new(vec![Value::Str("Alice".into())]));
+        assert!(v.size_hint() >= empty + 5);
+        let mut rec = Rec::new();
+        let empty = Value::Rec(rec.clone()).size_hint();
+        rec.set("name", Value::Str("Alice".into()));
+        assert!(Value::Rec(rec).size_hint() >= empty + 9);
+    }
+
+    #[test]
+    fn test_decimal_display() {
+        let d = |s: &str| s.parse::<Decimal>().unwrap();
+        assert_eq!("2.75m", Scalar::Dec(d("2.75m")).to_string());
+        assert_eq!("1.50m", Scalar::Dec(d("1.50M")).to_string());
+        assert_eq!("-0.05m", Scalar::Dec(d("-0.05")).to_string());
+        assert_eq!("1000.5m", Scalar::Dec(d("1_000.5m")).to_string());
+        assert_eq!("42m", Scalar::Dec(d("42m")).to_string());
+        assert_eq!("1500m", Scalar::Dec(d("1.5e3m")).to_string());
+        assert_eq!("0.015m", Scalar::Dec(d("1.5e-2m")).to_string());
+        assert_eq!(
+            "1,000.5m",
+            format_for_display(&Scalar::Dec(d("1000.5m")), &FormatOptions::en_US())
+        );
+    }
+
+    #[test]
+    fn test_decimal_parse_errors() {
+        for s in [
+            "",
+            "m",
+            ".5m",
+            "1.2.3m",
+            "1x",
+            "1e",
+            "1e99m",
+            "1.000000000000000000000000000000000000001",
+            "1.0e-2147483647",
+            "1.0e-2147483648",
+            "1e2147483647",
+        ] {
+            assert_eq!(
+                Err(ConversionError::InvalidDecimal),
+                s.parse::<Decimal>().map(|_| ())
+            );
+        }
+    }
+
+    #[test]
+    fn test_decimal_new() {
+        let v = Decimal::new(314, 2);
+        assert_eq!((314, 2), (v.mantissa(), v.scale()));
+        // Digits past the largest scale are rounded half up, away from zero
+        let v = Decimal::new(12345, Decimal::MAX_SCALE + 2);
+        assert_eq!((123, Decimal::MAX_SCALE), (v.mantissa(), v.scale()));
+        let round = |mantissa, excess| Decimal::new(mantissa, Decimal::MAX_SCALE + excess);
+        assert_eq!(124, round(12350, 2).mantissa());
+        assert_eq!(-124, round(-12350, 2).mantissa());
+        assert_eq!(-123, round(-12349, 2).mantissa());
+        assert_eq!(2, round(i128::MAX, Decimal::MAX_SCALE).mantissa());
+        assert_eq!(-2, round(i128::MIN, Decimal::MAX_SCALE).mantissa());
+        for scale in [2 * Decimal::MAX_SCALE + 1, u8::MAX] {
+            let v = Decimal::new(i128::MAX, scale);
+            assert_eq!((0, Decimal::MAX_SCALE), (v.mantissa(), v.scale()));
+        }
+    }
+
+    #[test]
+    fn test_decimal_arithmetic() {
+        let d = |s: &str| s.parse::<Decimal>().unwrap();
+        assert_eq!("3.75", d("1.25").saturating_add(d("2.5")).to_string());
+        assert_eq!("-1.25", d("1.25").saturating_sub(d("2.5")).to_string());
+        assert_eq!("3.125", d("1.25").saturating_mul(d("2.5")).to_string());
+        let max = Decimal::new(i128::MAX, 0);
+        let min = Decimal::new(i128::MIN, 0);
+        assert_eq!(i128::MAX, max.saturating_add(d("1")).mantissa());
+        assert_eq!(i128::MIN, min.saturating_sub(d("1")).mantissa());
+        assert_eq!(i128::MAX, max.saturating_mul(d("2")).mantissa());
+        assert_eq!(i128::MIN, max.saturating_mul(d("-2")).mantissa());
+        // Rescaling a huge value to a larger scale saturates as well
+        assert_eq!(i128::MAX, max.saturating_add(d("0.1")).mantissa());
+        let small = Decimal::new(1, Decimal::MAX_SCALE);
+        let product = small.saturating_mul(small);
+        assert_eq!(Decimal::MAX_SCALE, product.scale());
+        assert_eq!(0, product.mantissa());
+    }
+
+    #[test]
+    fn test_decimal_checked_arithmetic() {
+        let d = |s: &str| s.parse::<Decimal>().unwrap();
+        let s = |v: Option<Decimal>| v.unwrap().to_string();
+        assert_eq!("3.3", s(d("1.1").checked_add(d("2.2"))));
+        assert_eq!("-1.25", s(d("1.25").checked_sub(d("2.5"))));
+        assert_eq!("3.125", s(d("1.25").checked_mul(d("2.5"))));
+        assert_eq!("0.25", s(d("1.00").checked_div(d("4"))));
+        assert_eq!("2.5", s(d("10").checked_div(d("4"))));
+        assert_eq!("-20", s(d("10").checked_div(d("-0.5"))));
+        assert_eq!("1.00", s(d("2.00").checked_div(d("2"))));
+        // A quotient that does not terminate is rounded half up to the largest scale
+        let third = d("1").checked_div(d("3")).unwrap();
+        assert_eq!(Decimal::MAX_SCALE, third.scale());
+        assert_eq!(format!("0.{}", "3".repeat(38)), third.to_string());
+        let two_thirds = d("-2").checked_div(d("3")).unwrap();
+        assert_eq!(format!("-0.{}7", "6".repeat(37)), two_thirds.to_string());
+        // Digits that do not fit in the mantissa are rounded away
+        let big = Decimal::new(i128::MAX / 10 - 1, 0);
+        assert_eq!(big, big.checked_div(d("1")).unwrap());
+        assert_eq!(
+            "5671372782015641057722910123862803523.7",
+            s(big.checked_div(d("3")))
+        );
+        assert_eq!(None, d("1").checked_div(d("0.00")));
+        // A negative quotient can reach i128::MIN
+        let min = Decimal::new(i128::MIN, 0);
+        assert_eq!(Some(min), min.checked_div(d("1")));
+        assert_eq!(
+            Some(min),
+            Decimal::new(i128::MIN / 2, 0).checked_div(d("0.5"))
+        );
+        assert_eq!(
+            Some(min),
+            Decimal::new(i128::MAX / 2 + 1, 0).checked_div(d("-0.5"))
+        );
+        assert_eq!(None, min.checked_div(d("-1")));
+        // A product with a scale past the largest is rounded like a quotient
+        let product = Decimal::new(15, 20)
+            .checked_mul(Decimal::new(1, 19))
+            .unwrap();
+        assert_eq!(
+            (2, Decimal::MAX_SCALE),
+            (product.mantissa(), product.scale())
+        );
+        let product = Decimal::new(-15, 20)
+            .checked_mul(Decimal::new(1, 19))
+            .unwrap();
+        assert_eq!(-2, product.mantissa());
+        let product = Decimal::new(14, 20)
+            .checked_mul(Decimal::new(1, 19))
+            .unwrap();
+        assert_eq!(1, product.mantissa());
+        let max = Decimal::new(i128::MAX, 0);
+        assert_eq!(None, max.checked_add(d("1")));
+        assert_eq!(None, max.checked_add(d("0.1")));
+        assert_eq!(None, Decimal::new(i128::MIN, 0).checked_sub(d("1")));
+        assert_eq!(None, max.checked_mul(d("2")));
+        assert_eq!(None, max.checked_div(d("0.1")));
+    }
+
+    #[test]
+    fn test_scalar_from_dec_token() {
+        let tokens = lex_all("3.14m 1_000.50M 42").unwrap();
+        assert_eq!(
+            Ok(Scalar::Dec(Decimal::new(314, 2))),
+            Scalar::from_dec_token(&tokens[0])
+        );
+        let v = Scalar::from_dec_token(&tokens[1]).unwrap();
+        assert_eq!("1000.50m", v.to_string());
+        assert_eq!(
+            Err(ConversionError::InvalidDecimal),
+            Scalar::from_dec_token(&tokens[2])
+        );
+    }
+
+    fn one_of_each() -> Vec<Scalar> {
+        vec![
+            Scalar::Bool(true),
+            Scalar::Char('x'),
+            Scalar::Dec(Decimal::new(1, 0)),
+            Scalar::Flt32(1.0),
+            Scalar::Flt64(1.0),
+            Scalar::Int32(1),
+            Scalar::Int64(1),
+            Scalar::Null,
+            Scalar::Str("1".into()),
+        ]
+    }
+
+    #[test]
+    fn test_scalar_eq() {
+        let d = |s: &str| s.parse::<Decimal>().unwrap();
+        assert!(Scalar::Bool(true) == Scalar::Bool(true));
+        assert!(Scalar::Char('x') == Scalar::Char('x'));
+        assert!(Scalar::Dec(d("1.5m")) == Scalar::Dec(d("1.50m")));
+        assert!(Scalar::Flt32(2.75) == Scalar::Flt32(2.75));
+        assert!(Scalar::Flt64(2.75) == Scalar::Flt64(2.75));
+        assert!(Scalar::Int32(7) == Scalar::Int32(7));
+        assert!(Scalar::Int64(7) == Scalar::Int64(7));
+        assert!(Scalar::Null == Scalar::Null);
+        assert!(Scalar::Str("abc".into()) == Scalar::Str("abc".into()));
+        // NaN is equal to itself, and the zeros are distinct
+        assert!(Scalar::Flt32(f32::NAN) == Scalar::Flt32(f32::NAN));
+        assert!(Scalar::Flt64(f64::NAN) == Scalar::Flt64(f64::NAN));
+        assert!(Scalar::Flt64(0.0) != Scalar::Flt64(-0.0));
+    }
+
+    #[test]
+    fn test_scalar_eq_same_type_unequal() {
+        let d = |s: &str| s.parse::<Decimal>().unwrap();
+        assert!(Scalar::Bool(true) != Scalar::Bool(false));
+        assert!(Scalar::Char('x') != Scalar::Char('y'));
+        assert!(Scalar::Dec(d("1.5m")) != Scalar::Dec(d("1.05m")));
+        assert!(Scalar::Flt32(1.0) != Scalar::Flt32(2.0));
+        assert!(Scalar::Flt64(1.0) != Scalar::Flt64(2.0));
+        assert!(Scalar::Int32(1) != Scalar::Int32(2));
+        assert!(Scalar::Int64(1) != Scalar::Int64(2));
+        assert!(Scalar::Str("abc".into()) != Scalar::Str("abd".into()));
+    }
+
+    #[test]
+    fn test_scalar_eq_cross_type() {
+        for (i, a) in one_of_each().iter().enumerate() {
+            for (j, b) in one_of_each().iter().enumerate() {
+                assert_eq!(i == j, a == b, "{} == {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scalar_cmp_same_type() {
+        let d = |s: &str| Scalar::Dec(s.parse::<Decimal>().unwrap());
+        assert!(Scalar::Bool(false) < Scalar::Bool(true));
+        assert!(Scalar::Char('a') < Scalar::Char('b'));
+        assert!(d("1.5m") < d("1.55m"));
+        assert!(d("-2m") < d("1.5m"));
+        assert!(d("1.5m") <= d("1.50m"));
+        assert!(Scalar::Flt32(1.5) > Scalar::Flt32(-1.5));
+        assert!(Scalar::Flt64(1.5) >= Scalar::Flt64(1.5));
+        assert!(Scalar::Int32(-1) < Scalar::Int32(1));
+        assert!(Scalar::Int64(10) > Scalar::Int64(9));
+        assert!(Scalar::Null <= Scalar::Null);
+        assert!(Scalar::Str("abc".into()) < Scalar::Str("abd".into()));
+        assert_eq!(
+            Some(Ordering::Equal),
+            Scalar::cmp_same_type(&Scalar::Int32(3), &Scalar::Int32(3))
+        );
+    }
+
+    #[test]
+    fn test_scalar_cmp_not_comparable() {
+        for (i, a) in one_of_each().iter().enumerate() {
+            for (j, b) in one_of_each().iter().enumerate() {
+                assert_eq!(i == j, Scalar::cmp_same_type(a, b).is_some());
+            }
+        }
+        let nan = Scalar::Flt64(f64::NAN);
+        assert_eq!(None, Scalar::cmp_same_type(&nan, &Scalar::Flt64(1.0)));
+        assert_eq!(Some(Ordering::Equal), Scalar::cmp_same_type(&nan, &nan));
+        assert!(Scalar::Flt64(-0.0) < Scalar::Flt64(0.0));
+        assert_eq!(
+            None,
+            Scalar::cmp_same_type(&Scalar::Flt32(f32::NAN), &Scalar::Flt32(0.0))
+        );
+    }
+
+    #[test]
+    fn test_decimal_ord() {
+        let max = Decimal::new(i128::MAX, 0);
+        let min = Decimal::new(i128::MIN, 0);
+        let tiny = Decimal::new(1, Decimal::MAX_SCALE);
+        assert_eq!(Ordering::Greater, max.cmp(&tiny));
+        assert_eq!(Ordering::Less, min.cmp(&tiny));
+        assert_eq!(Ordering::Less, tiny.cmp(&max));
+        assert_eq!(Ordering::Greater, tiny.cmp(&min));
+        assert_eq!(
+            Ordering::Equal,
+            Decimal::new(150, 2).cmp(&Decimal::new(15, 1))
+        );
+    }
+
+    #[test]
+    fn test_scalar_hash_map() {
+        let d = |s: &str| Scalar::Dec(s.parse::<Decimal>().unwrap());
+        let keys = vec![
+            Scalar::Bool(false),
+            Scalar::Bool(true),
+            Scalar::Char('a'),
+            Scalar::Char('b'),
+            d("1.5m"),
+            d("2m"),
+            Scalar::Flt32(1.5),
+            Scalar::Flt32(f32::NAN),
+            Scalar::Flt64(0.0),
+            Scalar::Flt64(-0.0),
+            Scalar::Int32(1),
+            Scalar::Int32(2),
+            Scalar::Int64(1),
+            Scalar::Int64(2),
+            Scalar::Null,
+            Scalar::Str("a".into()),
+            Scalar::Str("b".into()),
+        ];
+        let mut map = HashMap::new();
+        for (i, k) in keys.into_iter().enumerate() {
+            assert_eq!(None, map.insert(k, i));
+        }
+        assert_eq!(17, map.len());
+        assert_eq!(Some(&0), map.get(&Scalar::Bool(false)));
+        assert_eq!(Some(&3), map.get(&Scalar::Char('b')));
+        // Equal decimals with different scales find the same entry
+        assert_eq!(Some(&4), map.get(&d("1.50m")));
+        assert_eq!(Some(&5), map.get(&d("2.000m")));
+        assert_eq!(Some(&7), map.get(&Scalar::Flt32(f32::NAN)));
+        assert_eq!(Some(&9), map.get(&Scalar::Flt64(-0.0)));
+        assert_eq!(Some(&10), map.get(&Scalar::Int32(1)));
+        assert_eq!(Some(&12), map.get(&Scalar::Int64(1)));
+        assert_eq!(Some(&14), map.get(&Scalar::Null));
+        assert_eq!(Some(&16), map.get(&Scalar::Str("b".into())));
+        assert_eq!(None, map.get(&Scalar::Str("c".into())));
+        assert_eq!(None, map.get(&Scalar::Int64(3)));
+    }
+
+    #[test]
+    fn test_value_from_scalar() {
+        for scalar in one_of_each() {
+            let text = scalar.to_string();
+            let value = Value::from(scalar.clone());
+            assert_eq!(text, value.to_string());
+            assert_eq!(scalar.size_hint(), value.size_hint());
+        }
+        assert_eq!(Value::Flt64(f64::NAN), Value::from(Scalar::Flt64(f64::NAN)));
+    }
+
+    #[test]
+    fn test_value_predicates() {
+        let v = Value::Bool(true);
+        assert!(v.is_bool() && !v.is_int() && !v.is_null());
+        assert!(Value::Int32(1).is_int() && Value::Int64(1).is_int());
+        assert!(Value::Flt32(1.0).is_flt() && Value::Flt64(1.0).is_flt());
+        assert!(!Value::Flt64(1.0).is_int());
+        assert!(Value::Null.is_null() && !Value::Null.is_bool());
+        assert!(Value::Str("".into()).is_str());
+        assert!(!Value::Char('x').is_str());
+        assert!(Value::Flt32(f32::NAN).is_nan() && Value::Flt64(f64::NAN).is_nan());
+        assert!(!Value::Flt64(f64::INFINITY).is_nan() && !Value::Int32(0).is_nan());
+    }
+
+    #[test]
+    fn test_value_type_name() {
+        let values = [
+            (Value::Bool(true), "bool"),
+            (Value::Char('x'), "char"),
+            (Value::Dec(Decimal::new(1, 0)), "dec"),
+            (Value::Flt32(1.0), "f32"),
+            (Value::Flt64(1.0), "f64"),
+            (Value::Int32(1), "i32"),
+            (Value::Int64(1), "i64"),
+            (Value::Null, "null"),
+            (Value::Str("".into()), "str"),
+            (Value::ActorRef(ActorRef::new(1)), "actor"),
+            (Value::Closure(Arc::new(closure())), "closure"),
+            (Value::List(List::new()), "list"),
+            (Value::Obj(Box::new(Obj::new(Rec::new()))), "obj"),
+            (Value::Rec(Rec::new()), "rec"),
+            (Value::Tuple(Tuple::new(vec![])), "tuple"),
+        ];
+        for (value, name) in values {
+            assert_eq!(name, value.type_name());
+        }
+    }
+
+    #[test]
+    fn test_value_clone_is_deep() {
+        let mut inner = Rec::new();
+        inner.set("n", int(1));
+        let mut list = List::from(vec![Value::Rec(inner.clone())]);
+        let original = Value::List(list.clone());
+        // Changing the copy and its nested record leaves the original unchanged
+        inner.set("n", int(2));
+        list.set(0, Value::Rec(inner)).unwrap();
+        list.push(int(3));
+        assert_eq!("[{n: 1}]", original.to_string());
+        assert_eq!("[{n: 2}, 3]", list.to_string());
+        assert_eq!(original, original.clone());
+    }
+
+    #[test]
+    fn test_obj_clone() {
+        let mut obj = Obj::new(Rec::new());
+        obj.define_method("incr", FuncRef(incr));
+        let mut copy = obj.clone();
+        assert_eq!(Ok(int(1)), copy.send("incr", &[]));
+        assert_eq!(None, obj.state().get("count"));
+        // The copy shares the method code
+        assert_eq!(Ok(int(1)), obj.send("incr", &[]));
+        let tuple = Tuple::new(vec![Value::Obj(Box::new(obj))]);
+        assert_eq!(tuple, tuple.clone());
+    }
+
+    #[test]
+    fn test_value_eq() {
+        let values: Vec<Value> = one_of_each()
+            .into_iter()
+            .map(Value::from)
+            .chain([
+                Value::ActorRef(ActorRef::new(1)),
+                Value::Closure(Arc::new(closure())),
+                Value::List(List::from(vec![int(1)])),
+                Value::Obj(Box::new(Obj::new(Rec::new()))),
+                Value::Rec(Rec::new()),
+                Value::Tuple(Tuple::new(vec![int(1)])),
+            ])
+            .collect();
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(i == j, a == b, "{} == {}", a, b);
+            }
+        }
+        assert_ne!(Value::Flt64(0.0), Value::Flt64(-0.0));
+    }
+
+    fn int(i: i32) -> Value {
+        Value::Int32(i)
+    }
+
+    #[test]
+    fn test_rec() {
+        let mut rec = Rec::new();
+        assert!(rec.is_empty());
+        rec.set("x", int(1));
+        rec.set("y", int(2));
+        assert_eq!(2, rec.len());
+        assert_eq!(Some(&int(1)), rec.get("x"));
+        assert_eq!(None, rec.get("z"));
+        // Setting an existing field replaces its value but keeps its position
+        rec.set("x", int(3));
+        assert_eq!(2, rec.len());
+        let fields: Vec<(&str, &Value)> = rec.fields().collect();
+        assert_eq!(vec![("x", &int(3)), ("y", &int(2))], fields);
+    }
+
+    #[test]
+    fn test_rec_display() {
+        assert_eq!("{}", Rec::new().to_string());
+        let mut inner = Rec::new();
+        inner.set("b", Value::Str("hi".into()));
+        let mut rec = Rec::new();
+        rec.set("a", int(1));
+        rec.set("inner", Value::Rec(inner));
+        assert_eq!(r#"{a: 1, inner: {b: "hi"}}"#, rec.to_string());
+    }
+
+    fn rec_of(fields: &[(&str, Value)]) -> Rec {
+        let mut rec = Rec::new();
+        for (name, value) in fields {
+            rec.set(name, value.clone());
+        }
+        rec
+    }
+
+    #[test]
+    fn test_rec_map_values() {
+        let rec = rec_of(&[("a", int(1)), ("b", int(2)), ("c", int(3))]);
+        let doubled = rec.map_values(|_, v| match v {
+            Value::Int32(i) => int(i * 2),
+            other => other.clone(),
+        });
+        assert_eq!("{a: 2, b: 4, c: 6}", doubled.to_string());
+        // The original is not changed
+        assert_eq!("{a: 1, b: 2, c: 3}", rec.to_string());
+        let named = rec.map_values(|name, _| Value::Str(name.into()));
+        assert_eq!(
+            rec_of(&[
+                ("a", Value::Str("a".into())),
+                ("b", Value::Str("b".into())),
+                ("c", Value::Str("c".into()))
+            ]),
+            named
+        );
+    }
+
+    #[test]
+    fn test_rec_filter_fields() {
+        let rec = rec_of(&[
+            ("name", Value::Str("Alice".into())),
+            ("age", int(30)),
+            ("city", Value::Str("Paris".into())),
+        ]);
+        let strs = rec.filter_fields(|_, v| matches!(v, Value::Str(_)));
+        assert_eq!(r#"{name: "Alice", city: "Paris"}"#, strs.to_string());
+        assert!(rec.filter_fields(|_, _| false).is_empty());
+        let no_age = rec.filter_fields(|name, _| name != "age");
+        assert_eq!(2, no_age.len());
+        assert_eq!(None, no_age.get("age"));
+    }
+
+    #[test]
+    fn test_rec_map_keys() {
+        let rec = rec_of(&[("a", int(1)), ("b", int(2))]);
+        let prefixed = rec.map_keys(|name| format!("x_{}", name));
+        assert_eq!("{x_a: 1, x_b: 2}", prefixed.to_string());
+        // On a collision, the last value wins
+        let collided = rec.map_keys(|_| "k".to_string());
+        assert_eq!("{k: 2}", collided.to_string());
+    }
+
+    #[test]
+    fn test_rec_reduce_values() {
+        let rec = rec_of(&[
+            ("a", int(1)),
+            ("b", Value::Str("two".into())),
+            ("c", Value::Int64(3)),
+            ("d", int(4)),
+        ]);
+        let sum = rec.clone().reduce_values(0i64, |acc, _, v| match v {
+            Value::Int32(i) => acc + i as i64,
+            Value::Int64(i) => acc + i,
+            _ => acc,
+        });
+        assert_eq!(8, sum);
+        let names = rec.reduce_values(String::new(), |acc, name, _| acc + name);
+        assert_eq!("abcd", names);
+    }
+
+    #[test]
+    fn test_rec_eq() {
+        let mut a = Rec::new();
+        a.set("x", int(1));
+        a.set("y", int(2));
+        let mut b = Rec::new();
+        b.set("y", int(2));
+        b.set("x", int(1));
+        assert_eq!(a, b);
+        assert_eq!(a, a.clone());
+        b.set("y", int(3));
+        assert_ne!(a, b);
+        b.set("y", int(2));
+        b.set("z", int(2));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_tuple() {
+        let t = Tuple::new(vec![int(1), int(2), int(3)]);
+        assert_eq!(3, t.len());
+        assert!(!t.is_empty());
+        assert_eq!(Some(&int(2)), t.get(1));
+        assert_eq!(None, t.get(3));
+        assert_eq!(None, t.get(usize::MAX));
+        assert_eq!(
+            vec![&int(1), &int(2), &int(3)],
+            t.iter().collect::<Vec<_>>()
+        );
+        let empty = Tuple::new(vec![]);
+        assert!(empty.is_empty());
+        assert_eq!(None, empty.get(0));
+    }
+
+    #[test]
+    fn test_tuple_display() {
+        assert_eq!("()", Tuple::new(vec![]).to_string());
+        let inner = Value::Tuple(Tuple::new(vec![int(2), int(3)]));
+        let t = Tuple::new(vec![int(1), inner, Value::Null]);
+        assert_eq!("(1, (2, 3), null)", t.to_string());
+    }
+
+    #[test]
+    fn test_tuple_zip_unzip() {
+        let s = |v: &str| Value::Str(v.into());
+        let numbers = Tuple::new(vec![int(1), int(2), int(3)]);
+        let letters = Tuple::new(vec![s("a"), s("b"), s("c")]);
+        let pairs = Tuple::zip(&numbers, &letters);
+        assert_eq!(r#"((1, "a"), (2, "b"), (3, "c"))"#, pairs.to_string());
+        assert_eq!(Ok((numbers.clone(), letters.clone())), Tuple::unzip(&pairs));
+        // The shorter tuple sets the length
+        let short = Tuple::new(vec![s("a")]);
+        assert_eq!(r#"((1, "a"))"#, Tuple::zip(&numbers, &short).to_string());
+        let empty = Tuple::new(vec![]);
+        assert!(Tuple::zip(&numbers, &empty).is_empty());
+        assert_eq!(Ok((empty.clone(), empty.clone())), Tuple::unzip(&empty));
+        let bad = Tuple::new(vec![
+            Value::Tuple(Tuple::new(vec![int(1), s("a")])),
+            Value::Tuple(Tuple::new(vec![int(2)])),
+        ]);
+        assert_eq!(
+            Err(UnzipError::ElementNotAPair { index: 1 }),
+            Tuple::unzip(&bad)
+        );
+        let bad = Tuple::new(vec![int(1)]);
+        assert_eq!(
+            Err(UnzipError::ElementNotAPair { index: 0 }),
+            Tuple::unzip(&bad)
+        );
+    }
+
+    #[test]
+    fn test_tuple_flat_map() {
+        let t = Tuple::new(vec![int(1), int(2), int(3)]);
+        let repeated = t.flat_map(|v| Tuple::new(vec![v.clone(), v.clone()]));
+        assert_eq!("(1, 1, 2, 2, 3, 3)", repeated.to_string());
+        let odd = t.flat_map(|v| match v {
+            Value::Int32(i) if i % 2 == 1 => Tuple::new(vec![v.clone()]),
+            _ => Tuple::new(vec![]),
+        });
+        assert_eq!("(1, 3)", odd.to_string());
+    }
+
+    #[test]
+    fn test_tuple_natives() {
+        let natives: HashMap<_, _> = TUPLE_NATIVES.into_iter().collect();
+        let a = Value::Tuple(Tuple::new(vec![int(1), int(2)]));
+        let b = Value::Tuple(Tuple::new(vec![Value::Bool(true), Value::Null]));
+        let pairs = natives["$tuple_zip"](&[a.clone(), b.clone()]).unwrap();
+        assert_eq!("((1, true), (2, null))", pairs.to_string());
+        let unzipped = natives["$tuple_unzip"](&[pairs]).unwrap();
+        assert_eq!(Value::Tuple(Tuple::new(vec![a.clone(), b])), unzipped);
+        assert_eq!(
+            Err(KlvmError::ElementNotAPair { index: 0 }),
+            natives["$tuple_unzip"](std::slice::from_ref(&a))
+        );
+        assert_eq!(
+            Err(KlvmError::TypeMismatch {
+                expected: "tuple",
+                found: "i32"
+            }),
+            natives["$tuple_zip"](&[a.clone(), int(1)])
+        );
+        assert_eq!(
+            Err(KlvmError::ArityMismatch {
+                expected: 1,
+                found: 2
+            }),
+            natives["$tuple_unzip"](&[a.clone(), a])
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let mut list = List::new();
+        assert!(list.is_empty());
+        list.push(int(1));
+        list.push(int(2));
+        list.push(int(3));
+        assert_eq!(3, list.len());
+        assert_eq!(Ok(&int(2)), list.get(1));
+        assert_eq!(Ok(()), list.set(1, int(20)));
+        assert_eq!(Ok(&int(20)), list.get(1));
+        assert_eq!(
+            vec![&int(1), &int(20), &int(3)],
+            list.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(Ok(List::from(vec![int(20), int(3)])), list.slice(1, 3));
+        assert_eq!(Ok(List::new()), list.slice(3, 3));
+        assert_eq!(Some(int(3)), list.pop());
+        assert_eq!(2, list.len());
+        assert_eq!("[1, 20]", list.to_string());
+        assert_eq!("[]", List::new().to_string());
+        assert_eq!(None, List::new().pop());
+    }
+
+    #[test]
+    fn test_list_index_out_of_bounds() {
+        let mut list = List::from(vec![int(1), int(2)]);
+        let err = KlvmError::IndexOutOfBounds { index: 2, len: 2 };
+        assert_eq!(Err(err.clone()), list.get(2));
+        assert_eq!(Err(err), list.set(2, int(3)));
+        assert_eq!(
+            Err(KlvmError::IndexOutOfBounds { index: 3, len: 2 }),
+            list.slice(0, 3)
+        );
+        assert_eq!(
+            Err(KlvmError::IndexOutOfBounds { index: 2, len: 2 }),
+            list.slice(2, 1)
+        );
+        assert_eq!(
+            "Index 2 is out of bounds for length 2",
+            list.get(2).unwrap_err().to_string()
+        );
+    }
+
+    fn incr(state: &mut Rec, args: &[Value]) -> Result<Value, KlvmError> {
+        let by = match args.first() {
+            Some(Value::Int32(i)) => *i,
+            _ => 1,
+        };
+        let count = match state.get("count") {
+            Some(Value::Int32(i)) => *i,
+            _ => 0,
+        };
+        state.set("count", int(count + by));
+        Ok(int(count + by))
+    }
+
+    #[test]
+    fn test_obj_send() {
+        let mut obj = Obj::new(Rec::new());
+        obj.define_method("incr", FuncRef(incr));
+        assert_eq!(Ok(int(1)), obj.send("incr", &[]));
+        assert_eq!(Ok(int(6)), obj.send("incr", &[int(5)]));
+        assert_eq!(Some(&int(6)), obj.state().get("count"));
+        assert_eq!("<obj {count: 6}>", obj.to_string());
+        assert_eq!(
+            Err(KlvmError::UnhandledMessage(Value::Str("decr".into()))),
+            obj.send("decr", &[])
+        );
+    }
+
+    #[test]
+    fn test_obj_eq() {
+        let mut a = Obj::new(Rec::new());
+        a.define_method("incr", FuncRef(incr));
+        let mut b = a.clone();
+        assert_eq!(a, b);
+        b.send("incr", &[]).unwrap();
+        assert_ne!(a, b);
+        a.state_mut().set("count", int(1));
+        assert_eq!(a, b);
+        b.define_method("reset", FuncRef(incr));
+        assert_ne!(a, b);
+    }
+
+    fn closure() -> Closure {
+        let mut env = Env::new();
+        env.bind("base", int(10));
+        Closure::new("f", 1, 3, vec!["x".into(), "y".into()], env)
+    }
+
+    #[test]
+    fn test_closure() {
+        let c = closure();
+        assert_eq!("f", c.func_name());
+        assert_eq!(1, c.func_id());
+        assert_eq!(3, c.start_pc());
+        let params: Vec<&str> = c.params().iter().map(|p| &**p).collect();
+        assert_eq!(vec!["x", "y"], params);
+        assert_eq!(Some(&int(10)), c.env().lookup("base"));
+        assert_eq!("<closure (x, y)>", c.to_string());
+        assert!(c.size_hint() > closure_without_env().size_hint());
+        // A closure is only equal to itself
+        let a = Value::Closure(Arc::new(c));
+        assert_eq!(a, a.clone());
+        assert_ne!(a, Value::Closure(Arc::new(closure())));
+    }
+
+    fn closure_without_env() -> Closure {
+        Closure::new("f", 1, 3, vec!["x".into(), "y".into()], Env::new())
+    }
+
+    #[test]
+    fn test_format_for_display() {
+        let v = Scalar::Flt64(1234567.89);
+        assert_eq!(
+            "1,234,567.89",
+            format_for_display(&v, &FormatOptions::en_US())
+        );
+        assert_eq!(
+            "1.234.567,89",
+            format_for_display(&v, &FormatOptions::de_DE())
+        );
+        assert_eq!(
+            "-1,234.0",
+            format_for_display(&Scalar::Flt64(-1234.0), &FormatOptions::en_US())
+        );
+        assert_eq!(
+            "123.5",
+            format_for_display(&Scalar::Flt64(123.5), &FormatOptions::en_US())
+        );
+        assert_eq!(
+            "1,000L",
+            format_for_display(&Scalar::Int64(1000), &FormatOptions::en_US())
+        );
+        assert_eq!(
+            "-100.000",
+            format_for_display(&Scalar::Int32(-100000), &FormatOptions::de_DE())
+        );
+        assert_eq!(
+            "1.5e20",
+            format_for_display(&Scalar::Flt64(1.5e20), &FormatOptions::en_US())
+        );
+        assert_eq!(
+            "true",
+            format_for_display(&Scalar::Bool(true), &FormatOptions::de_DE())
+        );
+    }
+
+    #[test]
+    fn test_format_for_display_default() {
+        let opts = FormatOptions::default();
+        let values = [
+            Scalar::Flt32(2.75),
+            Scalar::Flt64(1234567.89),
+            Scalar::Flt64(1e-7),
+            Scalar::Flt64(f64::NAN),
+            Scalar::Int32(-42),
+            Scalar::Int64(1234567),
+            Scalar::Char('x'),
+        ];
+        for v in values {
+            assert_eq!(v.to_string(), format_for_display(&v, &opts));
+        }
+    }
+
+    #[test]
+    fn test_format_for_display_precision() {
+        let opts = FormatOptions::default().precision(2);
+        assert_eq!("1.23", format_for_display(&Scalar::Flt64(1.23456), &opts));
+        assert_eq!("2.75f", format_for_display(&Scalar::Flt32(2.75), &opts));
+        assert_eq!("1.50e20", format_for_display(&Scalar::Flt64(1.5e20), &opts));
+        assert_eq!("42", format_for_display(&Scalar::Int32(42), &opts));
+        assert_eq!("42L", format_for_display(&Scalar::Int64(42), &opts));
+        let opts = FormatOptions::de_DE().precision(1);
+        assert_eq!(
+            "1.234.567,9",
+            format_for_display(&Scalar::Flt64(1234567.89), &opts)
+        );
+        let opts = FormatOptions {
+            use_scientific: false,
+            ..FormatOptions::en_US()
+        };
+        assert_eq!(
+            "100,000,000,000,000,000,000.0",
+            format_for_display(&Scalar::Flt64(1e20), &opts)
+        );
+    }
+
+    #[test]
+    fn test_int_to_char() {
+        let v = Scalar::Int32(65).int_to_char().unwrap();
+        assert!(matches!(v, Scalar::Char('A')));
+        let v = Scalar::Int32(0x1F980).int_to_char().unwrap();
+        assert!(matches!(v, Scalar::Char('🦀')));
+        assert_eq!(
+            Some(ConversionError::InvalidCodePoint(0x110000)),
+            Scalar::Int32(0x110000).int_to_char().err()
+        );
+        assert_eq!(
+            Some(ConversionError::InvalidCodePoint(0xD800)),
+            Scalar::Int32(0xD800).int_to_char().err()
+        );
+        assert_eq!(
+            Some(ConversionError::InvalidCodePoint(-1)),
+            Scalar::Int32(-1).int_to_char().err()
+        );
+        assert_eq!(
+            Some(ConversionError::NotAnInt32),
+            Scalar::Int64(65).int_to_char().err()
+        );
+    }
+
+    #[test]
+    fn test_char_int_round_trip() {
+        let next = match Scalar::Char('a').char_to_int().unwrap() {
+            Scalar::Int32(i) => Scalar::Int32(i + 1),
+            _ => unreachable!(),
+        };
+        assert!(matches!(next.int_to_char(), Ok(Scalar::Char('b'))));
+    }
+
+    #[test]
+    fn test_scalar_display() {
+        assert_eq!("true", Scalar::Bool(true).to_string());
+        assert_eq!("$'x'", Scalar::Char('x').to_string());
+        assert_eq!(r"$'\n'", Scalar::Char('\n').to_string());
+        assert_eq!(r"$'\''", Scalar::Char('\'').to_string());
+        assert_eq!("42", Scalar::Int32(42).to_string());
+        assert_eq!("42L", Scalar::Int64(42).to_string());
+        assert_eq!("null", Scalar::Null.to_string());
+        assert_eq!(r#""abc""#, Scalar::Str("abc".into()).to_string());
+        assert_eq!(
+            r#""say \"hi\"\n\\""#,
+            Scalar::Str("say \"hi\"\n\\".into()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_scalar_display_round_trip() {
+        let values = [
+            Scalar::Bool(false),
+            Scalar::Char('x'),
+            Scalar::Char('\\'),
+            Scalar::Char('\''),
+            Scalar::Char('\n'),
+            Scalar::Dec("2.750m".parse().unwrap()),
+            Scalar::Flt32(2.75),
+            Scalar::Flt64(1.5e20),
+            Scalar::Int32(42),
+            Scalar::Int64(42),
+            Scalar::Null,
+            Scalar::Str("".into()),
+            Scalar::Str(r#"a "quoted" \path\ and\ttab"#.into()),
+        ];
+        for v in values {
+            let text = v.to_string();
+            let tokens = lex_all(&text).unwrap();
+            assert_eq!(2, tokens.len(), "{}", text);
+            let token = &tokens[0];
+            assert_eq!(text, token.value);
+            let read_back = match token.token_type {
+                TokenType::Char => Scalar::Char(token.char_value(&text).unwrap()),
+                TokenType::Dec => Scalar::from_dec_token(token).unwrap(),
+                TokenType::Flt if token.value.ends_with('f') => {
+                    Scalar::Flt32(token.value.trim_end_matches('f').parse().unwrap())
+                }
+                TokenType::Flt => Scalar::Flt64(token.value.parse().unwrap()),
+                TokenType::Int if token.value.ends_with('L') => {
+                    Scalar::Int64(token.value.trim_end_matches('L').parse().unwrap())
+                }
+                TokenType::Int => Scalar::Int32(token.value.parse().unwrap()),
+                TokenType::Keyword if token.value == "null" => Scalar::Null,
+                TokenType::Keyword => Scalar::Bool(token.value.parse().unwrap()),
+                TokenType::Str => Scalar::Str(unescape(token).unwrap().into()),
+                other => panic!("Unexpected {:?} for {}", other, text),
+            };
+            assert!(v == read_back, "{}", text);
+        }
+        let text = Scalar::Int64(-7).to_string();
+        let tokens = lex_all(&text).unwrap();
+        assert_eq!(
+            vec!["-", "7L", "EOF"],
+            tokens.iter().map(|t| t.value).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_scalar_display_flt() {
+        assert_eq!("3.0", Scalar::Flt64(3.0).to_string());
+        assert_eq!("2.75", Scalar::Flt64(2.75).to_string());
+        assert_eq!("-2.5", Scalar::Flt64(-2.5).to_string());
+        assert_eq!("0.0", Scalar::Flt64(0.0).to_string());
+        assert_eq!("-0.0", Scalar::Flt64(-0.0).to_string());
+        assert_eq!("1.0e16", Scalar::Flt64(1e16).to_string());
+        assert_eq!("1.5e20", Scalar::Flt64(1.5e20).to_string());
+        assert_eq!("1.0e-7", Scalar::Flt64(1e-7).to_string());
+        assert_eq!("0.000001", Scalar::Flt64(1e-6).to_string());
+        assert_eq!("inf", Scalar::Flt64(f64::INFINITY).to_string());
+        assert_eq!("3.0f", Scalar::Flt32(3.0).to_string());
+        assert_eq!("2.75f", Scalar::Flt32(2.75).to_string());
+        assert_eq!("1.0e20f", Scalar::Flt32(1e20).to_string());
+    }
+
+    #[test]
+    fn test_scalar_display_flt64_round_trip() {
+        let values = [
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            2.75,
+            0.1 + 0.2,
+            1234567.89,
+            1e-6,
+            1e-7,
+            1e15,
+            1e16,
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            f64::MIN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ];
+        for v in values {
+            let s = Scalar::Flt64(v).to_string();
+            assert!(s.contains('.') || !v.is_finite());
+            assert_eq!(v, s.parse::<f64>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_scalar_size_hint() {
+        assert_eq!(4, Scalar::Bool(true).size_hint());
+        assert_eq!(4, Scalar::Char('x').size_hint());
+        assert_eq!(4, Scalar::Flt32(1.0).size_hint());
+        assert_eq!(4, Scalar::Int32(1).size_hint());
+        assert_eq!(8, Scalar::Flt64(1.0).size_hint());
+        assert_eq!(8, Scalar::Int64(1).size_hint());
+        assert_eq!(16, Scalar::Dec(Decimal::new(1, 0)).size_hint());
+        assert_eq!(0, Scalar::Null.size_hint());
+        assert_eq!(5, Scalar::Str("hello".into()).size_hint());
+        assert_eq!(8, Value::from(Scalar::Int64(1)).size_hint());
+    }
 }
@@ -0,0 +1,326 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+//! Serde support for `Value`, enabled by the `serde` feature. Values map to the JSON data model:
+//!
+//! * `Bool`, `Int32`, `Int64`, `Flt32`, `Flt64`, `Str`, and `Null` map to the matching primitive.
+//! * `Char` maps to a one-char string.
+//! * `Rec` maps to an object, and `Tuple` and `List` map to arrays.
+//! * `ActorRef` and `Closure` are only meaningful within a running program and cannot be
+//!   serialized.
+//! * `Dec` and `Obj` have no JSON equivalent and map to a tagged object, such as
+//!   `{"$type":"dec","value":"3.14m"}`. An object's value is its state record.
+//!
+//! A record field named `$type` and a non-finite float cannot be serialized, since they would not
+//! read back as the same value.
+//!
+//! Deserializing cannot recover every distinction. Integers become `Int32` when they fit and
+//! `Int64` otherwise, floats become `Flt64`, strings become `Str`, and arrays become `List`. A
+//! deserialized `Obj` has its state but no methods.
+
+use super::{Decimal, List, Obj, Rec, Value};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+const TYPE_KEY: &str = "$type";
+const VALUE_KEY: &str = "value";
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::Char(v) => serializer.serialize_char(*v),
+            Value::Dec(v) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry(TYPE_KEY, "dec")?;
+                map.serialize_entry(VALUE_KEY, &format!("{}m", v))?;
+                map.end()
+            }
+            Value::Flt32(v) if !v.is_finite() => Err(non_finite(*v)),
+            Value::Flt32(v) => serializer.serialize_f32(*v),
+            Value::Flt64(v) if !v.is_finite() => Err(non_finite(*v)),
+            Value::Flt64(v) => serializer.serialize_f64(*v),
+            Value::Int32(v) => serializer.serialize_i32(*v),
+            Value::Int64(v) => serializer.serialize_i64(*v),
+            Value::Null => serializer.serialize_unit(),
+            Value::Str(v) => serializer.serialize_str(v),
+            Value::ActorRef(_) => Err(ser::Error::custom(
+                "an actor reference cannot be serialized",
+            )),
+            Value::Closure(_) => Err(ser::Error::custom("a closure cannot be serialized")),
+            Value::List(v) => serialize_seq(v.iter(), v.len(), serializer),
+            Value::Obj(v) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry(TYPE_KEY, "obj")?;
+                map.serialize_entry(VALUE_KEY, v.state())?;
+                map.end()
+            }
+            Value::Rec(v) => v.serialize(serializer),
+            Value::Tuple(v) => serialize_seq(v.iter(), v.len(), serializer),
+        }
+    }
+}
+
+impl Serialize for Rec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (name, value) in self.fields() {
+            if name == TYPE_KEY {
+                let message = "a record field named $type cannot be serialized";
+                return Err(ser::Error::custom(message));
+            }
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+fn non_finite<E: ser::Error>(v: impl fmt::Display) -> E {
+    E::custom(format!("the non-finite float {} cannot be serialized", v))
+}
+
+fn serialize_seq<'a, S: Serializer>(
+    values: impl Iterator<Item = &'a Value>,
+    len: usize,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(len))?;
+    for value in values {
+        seq.serialize_element(value)?;
+    }
+    seq.end()
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a Torq value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        Ok(i32::try_from(v).map_or(Value::Int64(v), Value::Int32))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        let v = i64::try_from(v).map_err(|_| E::custom("integer is too large for i64"))?;
+        self.visit_i64(v)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::Flt64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::Str(v.into()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut list = List::new();
+        while let Some(value) = seq.next_element()? {
+            list.push(value);
+        }
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        // A record cannot have a "$type" field, so an object with one is tagged wherever the
+        // key appears
+        let mut entries = vec![];
+        while let Some(entry) = map.next_entry::<String, Value>()? {
+            entries.push(entry);
+        }
+        if entries.iter().any(|(name, _)| name == TYPE_KEY) {
+            return visit_tagged(entries);
+        }
+        let mut rec = Rec::new();
+        for (name, value) in entries {
+            rec.set(&name, value);
+        }
+        Ok(Value::Rec(rec))
+    }
+}
+
+/*
+ * Pre-condition:
+ *   `entries` are the entries of a tagged object, in any order.
+ */
+fn visit_tagged<E: de::Error>(entries: Vec<(String, Value)>) -> Result<Value, E> {
+    let mut type_name = None;
+    let mut value = None;
+    for (name, v) in entries {
+        match name.as_str() {
+            TYPE_KEY => type_name = Some(v),
+            VALUE_KEY => value = Some(v),
+            other => return Err(E::unknown_field(other, &[TYPE_KEY, VALUE_KEY])),
+        }
+    }
+    let value = value.ok_or_else(|| E::missing_field(VALUE_KEY))?;
+    let Some(Value::Str(type_name)) = type_name else {
+        return Err(E::custom("$type must be a string"));
+    };
+    match (&*type_name, value) {
+        ("dec", Value::Str(text)) => {
+            let dec: Decimal = text.parse().map_err(E::custom)?;
+            Ok(Value::Dec(dec))
+        }
+        ("dec", other) => Err(invalid_type(&other, "a decimal string")),
+        ("obj", Value::Rec(state)) => Ok(Value::Obj(Box::new(Obj::new(state)))),
+        ("obj", other) => Err(invalid_type(&other, "a record of object state")),
+        (other, _) => Err(E::unknown_variant(other, &["dec", "obj"])),
+    }
+}
+
+fn invalid_type<E: de::Error>(value: &Value, expected: &str) -> E {
+    E::invalid_type(de::Unexpected::Other(value.type_name()), &expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::klvm::actor::ActorRef;
+    use crate::klvm::env::Env;
+    use crate::klvm::value::{Closure, Tuple};
+    use std::sync::Arc;
+
+    fn to_json(value: &Value) -> String {
+        serde_json::to_string(value).unwrap()
+    }
+
+    fn from_json(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_serialize_scalars() {
+        assert_eq!("true", to_json(&Value::Bool(true)));
+        assert_eq!(r#""x""#, to_json(&Value::Char('x')));
+        assert_eq!(
+            r#"{"$type":"dec","value":"2.75m"}"#,
+            to_json(&Value::Dec("2.75m".parse().unwrap()))
+        );
+        assert_eq!("2.5", to_json(&Value::Flt32(2.5)));
+        assert_eq!("2.75", to_json(&Value::Flt64(2.75)));
+        assert_eq!("-7", to_json(&Value::Int32(-7)));
+        assert_eq!("9000000000", to_json(&Value::Int64(9_000_000_000)));
+        assert_eq!("null", to_json(&Value::Null));
+        assert_eq!(r#""a\"b""#, to_json(&Value::Str("a\"b".into())));
+    }
+
+    #[test]
+    fn test_serialize_composites() {
+        let mut rec = Rec::new();
+        rec.set("a", Value::Int32(1));
+        rec.set("b", Value::List(List::from(vec![Value::Null])));
+        assert_eq!(r#"{"a":1,"b":[null]}"#, to_json(&Value::Rec(rec.clone())));
+        let tuple = Tuple::new(vec![Value::Int32(1), Value::Bool(false)]);
+        assert_eq!("[1,false]", to_json(&Value::Tuple(tuple)));
+        assert_eq!(
+            r#"{"$type":"obj","value":{"a":1,"b":[null]}}"#,
+            to_json(&Value::Obj(Box::new(Obj::new(rec))))
+        );
+    }
+
+    #[test]
+    fn test_serialize_errors() {
+        let mut rec = Rec::new();
+        rec.set("$type", Value::Str("dec".into()));
+        rec.set("value", Value::Str("1m".into()));
+        assert!(serde_json::to_string(&Value::Rec(rec.clone())).is_err());
+        assert!(serde_json::to_string(&Value::Obj(Box::new(Obj::new(rec)))).is_err());
+        for value in [
+            Value::Flt32(f32::NAN),
+            Value::Flt32(f32::INFINITY),
+            Value::Flt64(f64::NAN),
+            Value::Flt64(f64::NEG_INFINITY),
+        ] {
+            assert!(serde_json::to_string(&value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_serialize_closure() {
+        let closure = Closure::new("f", 0, 0, vec![], Env::new());
+        let value = Value::Closure(Arc::new(closure));
+        assert!(serde_json::to_string(&value).is_err());
+    }
+
+    #[test]
+    fn test_serialize_actor_ref() {
+        let value = Value::ActorRef(ActorRef::new(1));
+        assert!(serde_json::to_string(&value).is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        assert_eq!(Value::Bool(false), from_json("false"));
+        assert_eq!(Value::Int32(-7), from_json("-7"));
+        assert_eq!(Value::Int64(9_000_000_000), from_json("9000000000"));
+        assert_eq!(Value::Flt64(2.75), from_json("2.75"));
+        assert_eq!(Value::Null, from_json("null"));
+        assert_eq!(Value::Str("x".into()), from_json(r#""x""#));
+        assert_eq!(
+            Value::Dec("2.75m".parse().unwrap()),
+            from_json(r#"{"$type":"dec","value":"2.75m"}"#)
+        );
+        let mut rec = Rec::new();
+        rec.set("a", Value::Int32(1));
+        rec.set("b", Value::List(List::from(vec![Value::Null])));
+        assert_eq!(Value::Rec(rec.clone()), from_json(r#"{"a":1,"b":[null]}"#));
+        assert_eq!(
+            Value::Obj(Box::new(Obj::new(rec))),
+            from_json(r#"{"$type":"obj","value":{"a":1,"b":[null]}}"#)
+        );
+        assert_eq!(Value::Rec(Rec::new()), from_json("{}"));
+        // The keys of a tagged object can come in either order
+        assert_eq!(
+            Value::Dec("2.75m".parse().unwrap()),
+            from_json(r#"{"value":"2.75m","$type":"dec"}"#)
+        );
+        let mut rec = Rec::new();
+        rec.set("value", Value::Int32(1));
+        rec.set("other", Value::Null);
+        assert_eq!(Value::Rec(rec), from_json(r#"{"value":1,"other":null}"#));
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        for json in [
+            r#"{"$type":"dec","value":"abc"}"#,
+            r#"{"$type":"dec"}"#,
+            r#"{"$type":"obj","value":1}"#,
+            r#"{"$type":"set","value":[]}"#,
+            r#"{"value":"1m","$type":"dec","other":1}"#,
+            r#"{"$type":1,"value":"1m"}"#,
+            "18446744073709551615",
+        ] {
+            assert!(serde_json::from_str::<Value>(json).is_err(), "{}", json);
+        }
+    }
+}
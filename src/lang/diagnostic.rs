@@ -0,0 +1,130 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+//! Diagnostic is the common form of errors and warnings reported against source text. Each phase
+//! converts its own error type into a `Diagnostic` so that `render_diagnostic` can display them
+//! all the same way.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticKind {
+    Error,
+    Warning,
+}
+
+/// Byte range `start_byte..end_byte` in the source, plus the one-based line and column of
+/// `start_byte`. Column counts chars and not bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start_byte: u32,
+    pub end_byte: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub span: Span,
+    pub message: String,
+    pub hint: Option<String>,
+    pub source_name: Option<String>,
+}
+
+/// Render `diagnostic` with the offending line from `source` and a caret marking the span:
+///
+/// ```text
+/// error: Invalid integer
+///  --> main.torq:2:5
+///   |
+/// 2 | x = 12a
+///   |     ^
+///   = hint: Integers contain only digits
+/// ```
+pub fn render_diagnostic(diagnostic: &Diagnostic, source: &str) -> String {
+    let kind = match diagnostic.kind {
+        DiagnosticKind::Error => "error",
+        DiagnosticKind::Warning => "warning",
+    };
+    let span = &diagnostic.span;
+    let name = diagnostic.source_name.as_deref().unwrap_or("<source>");
+    let line_number = span.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    let mut answer = format!(
+        "{}: {}\n{}--> {}:{}:{}\n",
+        kind, diagnostic.message, gutter, name, span.line, span.column
+    );
+    if let Some(line) = source.lines().nth(span.line.saturating_sub(1) as usize) {
+        let indent = span.column.saturating_sub(1) as usize;
+        let remaining = line.chars().count().saturating_sub(indent);
+        let width = (span.end_byte.saturating_sub(span.start_byte) as usize)
+            .min(remaining)
+            .max(1);
+        answer.push_str(&format!("{} |\n", gutter));
+        answer.push_str(&format!("{} | {}\n", line_number, line));
+        answer.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(indent),
+            "^".repeat(width)
+        ));
+    }
+    if let Some(hint) = &diagnostic.hint {
+        answer.push_str(&format!("{} = hint: {}\n", gutter, hint));
+    }
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(span: Span, hint: Option<&str>) -> Diagnostic {
+        Diagnostic {
+            kind: DiagnosticKind::Error,
+            span,
+            message: "Invalid integer".to_string(),
+            hint: hint.map(ToOwned::to_owned),
+            source_name: Some("main.torq".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_render_diagnostic() {
+        let source = "var a = 1\nx = 12a\n";
+        let span = Span {
+            start_byte: 14,
+            end_byte: 17,
+            line: 2,
+            column: 5,
+        };
+        let d = diagnostic(span, Some("Integers contain only digits"));
+        let expected = "error: Invalid integer\n \
+                        --> main.torq:2:5\n  \
+                        |\n\
+                        2 | x = 12a\n  \
+                        |     ^^^\n  \
+                        = hint: Integers contain only digits\n";
+        assert_eq!(expected, render_diagnostic(&d, source));
+    }
+
+    #[test]
+    fn test_render_diagnostic_without_source_line() {
+        let span = Span {
+            start_byte: 0,
+            end_byte: 1,
+            line: 9,
+            column: 1,
+        };
+        let mut d = diagnostic(span, None);
+        d.kind = DiagnosticKind::Warning;
+        d.source_name = None;
+        assert_eq!(
+            "warning: Invalid integer\n --> <source>:9:1\n",
+            render_diagnostic(&d, "x")
+        );
+    }
+}
@@ -10,6 +10,9 @@
 //!
 //! Max source size is 2,147,483,648 bytes.
 
+use crate::lang::diagnostic::{Diagnostic, DiagnosticKind, Span};
+use std::fmt;
+use std::ops::Range;
 use std::str::Chars;
 
 #[cfg(test)]
@@ -20,12 +23,18 @@ const COMMENT_IS_MISSING_CLOSING_SEQUENCE: &str = "Comment is missing closing se
 const FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF: &str = "Floating point suffix must be one of [fFdDmM]";
 const IDENT_IS_MISSING_CLOSING_BACKTICK: &str = "Identifier is missing closing backtick";
 const INTEGER_SUFFIX_MUST_BE_ONE_OF: &str = "Integer suffix must be one of [lLmM]";
+const INVALID_BINARY_NUMBER: &str = "Invalid binary number";
+const INVALID_CHAR_LITERAL: &str = "Char literal must contain exactly one char or escape sequence";
 const INVALID_DECIMAL_NUMBER: &str = "Invalid decimal number";
 const INVALID_FLOATING_POINT_NUMBER: &str = "Invalid floating point number";
 const INVALID_HEXADECIMAL_NUMBER: &str = "Invalid hexadecimal number";
 const INVALID_INTEGER_NUMBER: &str = "Invalid integer";
+const INVALID_OCTAL_NUMBER: &str = "Invalid octal number";
+const INVALID_UNICODE_ESCAPE: &str = "Unicode escape must be \\u with 4 hex digits or \\U with 8";
+const MISPLACED_DIGIT_SEPARATOR: &str = "Digit separator '_' must be between two digits";
 const STR_IS_MISSING_CLOSING_DOUBLE_QUOTE: &str = "String is missing closing double quote";
 const STR_IS_MISSING_CLOSING_SINGLE_QUOTE: &str = "String is missing closing single quote";
+const STR_IS_MISSING_CLOSING_TAG: &str = "String is missing closing heredoc tag";
 const UNRECOGNIZED_TOKEN: &str = "Unrecognized token";
 
 // KEYWORDS
@@ -83,6 +92,10 @@ const TELL_VALUE: &str = "tell";
 //                         01 2345678901234567890123456
 const DELIMITERS: &[u8] = "!\"#%&'()*+,-./:;<=>@[]`{|}~".as_bytes();
 
+// All delimiters are ASCII, so a 128-bit mask with one bit per code point answers "is this a
+// delimiter?" without searching.
+const DELIMITER_MASK: u128 = delimiter_mask(DELIMITERS);
+
 // There are 25 one char symbols below correlated to their position in the delimiter array.
 // Positions 4 and 24 are blank (not used).
 //                                         1         2
@@ -121,6 +134,16 @@ const TWO_CHAR_SYMBOLS: [[u8; 2]; 27] = [
     [b' ', b' '], // 26: not used
 ];
 
+const fn delimiter_mask(delimiters: &[u8]) -> u128 {
+    let mut mask = 0u128;
+    let mut i = 0;
+    while i < delimiters.len() {
+        mask |= 1 << delimiters[i];
+        i += 1;
+    }
+    mask
+}
+
 const EOF_TOKEN: Token = Token {
     value: "EOF",
     byte_index: -1,
@@ -130,14 +153,19 @@ const EOF_TOKEN: Token = Token {
 /// Character position is the UTF-8 character index and not the byte index. Because UTF-8
 /// character encodings vary between 1 and 4 bytes, character positions in a source string are not
 /// deterministic.
+///
+/// The layout is fixed with `repr(C)` so that the 1-byte token type packs into the 4 bytes
+/// following the 4-byte byte index: 16 (value) + 4 (byte index) + 1 (token type) + 3 (padding).
 #[derive(Debug, PartialEq)]
+#[repr(C)]
 pub struct Token<'a> {
     pub value: &'a str,
     pub byte_index: i32,
     pub token_type: TokenType,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
 pub enum TokenType {
     Char,
     Comment,
@@ -151,6 +179,7 @@ pub enum TokenType {
     Str,
     ThreeCharSym,
     TwoCharSym,
+    WeakKeyword,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -158,6 +187,9 @@ struct LexerIndex {
     char: char,
     char_index: i32,
     byte_index: i32,
+    // One-based line and column, where column counts chars and not bytes
+    line: u32,
+    column: u32,
 }
 
 pub struct LexerIter<'a> {
@@ -165,6 +197,10 @@ pub struct LexerIter<'a> {
     str_iter: Chars<'a>,
     current: Option<LexerIndex>,
     current_plus_1: Option<LexerIndex>,
+    // Set once `Iterator::next` has returned the EOF token
+    eof_returned: bool,
+    // Result lexed by `peek` and not yet consumed by `next`
+    peeked: Option<Result<Token<'a>, LexerError>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -173,31 +209,342 @@ pub struct LexerError {
     index: LexerIndex,
 }
 
+impl LexerError {
+    /// Byte index of the error in the source, the same as [`LexerError::byte_offset`] but typed
+    /// like `Token::byte_index`.
+    pub fn byte_index(&self) -> i32 {
+        self.index.byte_index
+    }
+
+    /// Byte offset of the error in the source.
+    pub fn byte_offset(&self) -> u32 {
+        self.index.byte_index as u32
+    }
+
+    /// One-based line number of the error in the source.
+    pub fn line(&self) -> u32 {
+        self.index.line
+    }
+
+    /// One-based column of the error in the source, counted in chars and not bytes.
+    pub fn column(&self) -> u32 {
+        self.index.column
+    }
+
+    /// Convert into a `Diagnostic` spanning the char where the error was found.
+    pub fn into_diagnostic(self, source_name: Option<&str>) -> Diagnostic {
+        let start_byte = self.byte_offset();
+        Diagnostic {
+            kind: DiagnosticKind::Error,
+            span: Span {
+                start_byte,
+                end_byte: start_byte + self.index.char.len_utf8() as u32,
+                line: self.line(),
+                column: self.column(),
+            },
+            message: self.message.to_string(),
+            hint: Some(self.recovery_hint().to_string()),
+            source_name: source_name.map(ToOwned::to_owned),
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        self.message
+    }
+
+    /// Format the error with the line of `source` that contains it and a caret under the
+    /// offending char:
+    ///
+    /// ```text
+    /// error at 3:7: Unrecognized token
+    ///   | foo ^bar baz
+    ///   |     ^
+    /// ```
+    ///
+    /// Line and column are computed from `source`. A byte index past the end of `source` is placed
+    /// at its end, so this never panics.
+    pub fn with_source_context(&self, source: &str) -> String {
+        let byte = clamp_to_char_boundary(source, self.index.byte_index);
+        let line_start = source[..byte].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[byte..].find('\n').map_or(source.len(), |i| byte + i);
+        let (line, column) = line_and_column(source, byte);
+        let (line, column) = (line as usize, column as usize);
+        format!(
+            "error at {}:{}: {}\n  | {}\n  | {}^",
+            line,
+            column,
+            self.message,
+            source[line_start..line_end].trim_end_matches('\r'),
+            " ".repeat(column - 1)
+        )
+    }
+
+    /// A short suggestion for fixing the error.
+    pub fn recovery_hint(&self) -> &'static str {
+        match self.message {
+            COMMENT_IS_MISSING_CLOSING_SEQUENCE => "Close the block comment with '*/'",
+            FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF => {
+                "Remove the suffix or use f or F (32 bits), d or D (64 bits), or m or M (decimal)"
+            }
+            IDENT_IS_MISSING_CLOSING_BACKTICK => "Close the identifier with a backtick '`'",
+            INTEGER_SUFFIX_MUST_BE_ONE_OF => {
+                "Remove the suffix or use l or L (64 bits), or m or M (decimal)"
+            }
+            INVALID_CHAR_LITERAL => {
+                "Put one char or escape sequence between the quotes, such as $'a' or $'\\n'"
+            }
+            INVALID_DECIMAL_NUMBER | INVALID_INTEGER_NUMBER => {
+                "Separate the number from what follows with whitespace or a symbol"
+            }
+            INVALID_FLOATING_POINT_NUMBER => {
+                "Follow the decimal point and any exponent marker with at least one digit"
+            }
+            INVALID_BINARY_NUMBER => "Use only the digits 0 and 1 after '0b'",
+            INVALID_HEXADECIMAL_NUMBER => "Use only the digits 0-9, a-f, and A-F after '0x'",
+            INVALID_OCTAL_NUMBER => "Use only the digits 0-7 after '0o'",
+            INVALID_UNICODE_ESCAPE => {
+                "Write \\u0041 or \\U0001F600 using exactly 4 or 8 hex digits"
+            }
+            MISPLACED_DIGIT_SEPARATOR => {
+                "Remove the '_' or move it so that a digit comes before and after it"
+            }
+            STR_IS_MISSING_CLOSING_DOUBLE_QUOTE => "Close the string with a double quote '\"'",
+            STR_IS_MISSING_CLOSING_SINGLE_QUOTE => "Close the string with a single quote '\''",
+            STR_IS_MISSING_CLOSING_TAG => {
+                "Close the heredoc with a line containing only its tag, with no indentation"
+            }
+            _ => "Remove the char or put it inside a string or quoted identifier",
+        }
+    }
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Lexer error at byte {}: {}",
+            self.index.byte_index, self.message
+        )
+    }
+}
+
+impl std::error::Error for LexerError {}
+
+/*
+ * Return `byte_index` as an index into `source`, moved to the end of `source` if it is past it
+ * and back to the start of a char if it is inside one. A negative index is treated as zero.
+ */
+fn clamp_to_char_boundary(source: &str, byte_index: i32) -> usize {
+    let mut byte = (byte_index.max(0) as usize).min(source.len());
+    while !source.is_char_boundary(byte) {
+        byte -= 1;
+    }
+    byte
+}
+
+/*
+ * Pre-condition: `byte` is a char boundary of `source`.
+ *
+ * Return the one-based line and column, counted in chars, of `byte` in `source`.
+ */
+fn line_and_column(source: &str, byte: usize) -> (u32, u32) {
+    let line_start = source[..byte].rfind('\n').map_or(0, |i| i + 1);
+    let line = source[..line_start].matches('\n').count() + 1;
+    let column = source[line_start..byte].chars().count() + 1;
+    (line as u32, column as u32)
+}
+
+/*
+ * Pre-condition: `content` is the text between the quotes of a `$'c'` char literal.
+ *
+ * Return `None` unless `content` is exactly one char or one of the escape sequences `\n`, `\t`,
+ * `\\`, `\'`, or `\u{XXXX}` with 1 to 6 hex digits naming a valid code point.
+ */
+fn decode_char_literal(content: &str) -> Option<char> {
+    let mut chars = content.chars();
+    let c = match chars.next()? {
+        '\\' => match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            '\\' => '\\',
+            '\'' => '\'',
+            'u' => {
+                let hex = chars.as_str().strip_prefix('{')?.strip_suffix('}')?;
+                if hex.is_empty() || hex.len() > 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return None;
+                }
+                return char::from_u32(u32::from_str_radix(hex, 16).ok()?);
+            }
+            _ => return None,
+        },
+        c => c,
+    };
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(c)
+}
+
+impl<'a> Token<'a> {
+    /// Return the Torq source text that reconstructs this token.
+    ///
+    /// Strings are normalized to double quotes. A backtick-quoted identifier is unquoted when its
+    /// content is a valid plain identifier, unless `force_quote_idents` is true or the content is
+    /// a keyword or weak keyword that would be misread without its backticks.
+    pub fn to_display_string(&self, force_quote_idents: bool) -> String {
+        match self.token_type {
+            TokenType::Eof => String::new(),
+            TokenType::Ident if self.value.starts_with('`') => {
+                let content = &self.value[1..self.value.len() - 1];
+                if force_quote_idents || !Self::is_plain_ident(content) {
+                    self.value.to_string()
+                } else {
+                    content.to_string()
+                }
+            }
+            TokenType::Str if self.value.starts_with('\'') => {
+                Self::single_to_double_quoted(self.value)
+            }
+            _ => self.value.to_string(),
+        }
+    }
+
+    /// Return the char denoted by a `$'c'` char literal token, with its escape sequence processed.
+    /// `source` is the text the token was read from, and is used to locate an error.
+    pub fn char_value(&self, source: &str) -> Result<char, LexerError> {
+        let content = match self.token_type {
+            TokenType::Char => self
+                .value
+                .strip_prefix("$'")
+                .and_then(|v| v.strip_suffix('\'')),
+            _ => None,
+        };
+        content.and_then(decode_char_literal).ok_or_else(|| {
+            let (line, column) =
+                line_and_column(source, clamp_to_char_boundary(source, self.byte_index));
+            LexerError {
+                message: INVALID_CHAR_LITERAL,
+                index: LexerIndex {
+                    char: self.value.chars().next().unwrap_or('\0'),
+                    char_index: -1,
+                    byte_index: self.byte_index,
+                    line,
+                    column,
+                },
+            }
+        })
+    }
+
+    /// Return the byte range of this token in its source, so that `&source[token.byte_range()]`
+    /// equals `token.value`. The EOF token is not in the source and returns the empty range `0..0`.
+    pub fn byte_range(&self) -> Range<usize> {
+        if self.byte_index < 0 {
+            return 0..0;
+        }
+        let start = self.byte_index as usize;
+        start..start + self.value.len()
+    }
+
+    /// Number of chars, not bytes, in this token.
+    pub fn char_len(&self) -> usize {
+        self.value.chars().count()
+    }
+
+    /// Return the lines between the opening `<<TAG` line and the closing `TAG` line of a heredoc
+    /// string, without the newline that precedes the closing tag. Return `None` for other tokens.
+    pub fn heredoc_body(&self) -> Option<&'a str> {
+        if self.token_type != TokenType::Str || !self.value.starts_with("<<") {
+            return None;
+        }
+        let first_newline = self.value.find('\n')?;
+        let last_newline = self.value.rfind('\n')?;
+        if first_newline == last_newline {
+            return Some("");
+        }
+        Some(&self.value[first_newline + 1..last_newline])
+    }
+
+    fn is_plain_ident(content: &str) -> bool {
+        let mut chars = content.chars();
+        match chars.next() {
+            Some(c) if !LexerIter::is_digit(c) && LexerIter::is_keyword_or_ident_char(c) => {}
+            _ => return false,
+        }
+        if !chars.all(LexerIter::is_keyword_or_ident_char) {
+            return false;
+        }
+        let lexer_iter = LexerIter::new(content);
+        let stop = content.len() - 1;
+        !lexer_iter.is_keyword(0, stop) && !lexer_iter.is_weak_keyword(0, stop)
+    }
+
+    fn single_to_double_quoted(value: &str) -> String {
+        let content = &value[1..value.len() - 1];
+        let mut result = String::with_capacity(value.len() + 2);
+        result.push('"');
+        let mut chars = content.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                // An escaped single quote no longer needs escaping
+                match chars.next() {
+                    Some('\'') => result.push('\''),
+                    Some(escaped) => {
+                        result.push('\\');
+                        result.push(escaped);
+                    }
+                    None => result.push('\\'),
+                }
+            } else if c == '"' {
+                // A bare double quote must now be escaped
+                result.push_str("\\\"");
+            } else {
+                result.push(c);
+            }
+        }
+        result.push('"');
+        result
+    }
+}
+
 ///
 /// # Overview
 ///
 /// ## Numbers
 /// * Begin with a digit.
 /// * Hex numbers begin with "0x" or "0X".
+/// * Binary numbers begin with "0b" or "0B".
+/// * Octal numbers begin with "0o" or "0O".
 /// * Floating point numbers begin with a non-zero digit and have a fractional part.
 /// * Scientific notation has an exponent marker "e" or "E" immediately after the fractional part.
 /// * Scientific notation has an optional sign "-" or "+" after the exponent marker.
 /// * Scientific notation has an exponent integer after the exponent designator or exponent sign.
 /// * Any floating point number can be declared 64 bits with a suffix "d" or "D".
 /// * Any floating point number can be declared 32 bits with a suffix "f" or "F".
+/// * Digits may be grouped with "_", such as "1_000_000", but each "_" must be between two digits.
+///   The token value keeps the underscores.
 /// * Numbers end with a separator comprised of whitespace or a delimiter.
 ///
 /// ## Symbols
 /// * Comprised of delimiters of one, two, or three chars in size.
 /// * Parse eagerly such that "...." is parsed as "..." and "." instead of "." and "...".
 ///
+/// ## Chars
+/// * Begin with "$" immediately followed by a single quote, such as `$'a'`.
+/// * Contain exactly one char or one escape sequence: `\n`, `\t`, `\\`, `\'`, or `\u{XXXX}`.
+///
 /// ## Strings
 /// * Quoted using double (") or single (') quotes.
 /// * Quote chars and other special chars are escaped using the backslash (\) char.
 /// * Backslash is escaped using a backslash.
+/// * Unicode escapes are "\u" followed by 4 hex digits or "\U" followed by 8 hex digits. The lexer
+///   checks their form, and `token_value::unescape` decodes them.
+/// * A heredoc begins with "<<TAG" and ends with a line containing only "TAG". The lines between
+///   are taken as-is, without escapes.
 ///
 /// ## Keywords
 /// * Well-known alphanumeric char sequences.
+/// * Weak keywords "as", "ask", "handle", and "tell" are keywords only in certain contexts. They
+///   are returned as `WeakKeyword` so the parser can decide whether to treat them as identifiers.
 ///
 /// ## Identifiers
 /// * Alphanumeric char sequences not first recognized as identifiers.
@@ -208,23 +555,58 @@ pub struct LexerError {
 /// * Block comments begin with a "/*" char sequence and terminate with a "*/" char sequence.
 ///
 impl<'a> LexerIter<'a> {
+    /// Return the byte index of the char at `char_index` in `source`. The char index one past the
+    /// last char maps to `source.len()` so that an end-of-source cursor can be translated. Return
+    /// `None` if `char_index` is beyond that.
+    pub fn char_index_to_byte_index(source: &str, char_index: usize) -> Option<usize> {
+        source
+            .char_indices()
+            .map(|(byte_index, _)| byte_index)
+            .chain(std::iter::once(source.len()))
+            .nth(char_index)
+    }
+
+    /// Return the char index of the char starting at `byte_index` in `source`. The byte index
+    /// `source.len()` maps to the char count. Return `None` if `byte_index` is beyond the source
+    /// or falls inside a multi-byte char.
+    pub fn byte_index_to_char_index(source: &str, byte_index: usize) -> Option<usize> {
+        if !source.is_char_boundary(byte_index) {
+            return None;
+        }
+        Some(source[..byte_index].chars().count())
+    }
+
     fn current(&self) -> Option<LexerIndex> {
         self.current
     }
 
+    fn current_byte_index(&self) -> usize {
+        self.current
+            .map_or(self.source.len(), |idx| idx.byte_index as usize)
+    }
+
     fn fetch_next_char(str_iter: &mut Chars, current: &Option<LexerIndex>) -> Option<LexerIndex> {
         if let Some(next_char) = str_iter.next() {
             if let Some(current) = current {
+                let (line, column) = if current.char == '\n' {
+                    (current.line + 1, 1)
+                } else {
+                    (current.line, current.column + 1)
+                };
                 Some(LexerIndex {
                     char: next_char,
                     char_index: current.char_index + 1,
                     byte_index: current.byte_index + current.char.len_utf8() as i32,
+                    line,
+                    column,
                 })
             } else {
                 Some(LexerIndex {
                     char: next_char,
                     char_index: 0,
                     byte_index: 0,
+                    line: 1,
+                    column: 1,
                 })
             }
         } else {
@@ -236,21 +618,34 @@ impl<'a> LexerIter<'a> {
      * Return 0 through 26 if delimiter is found. Otherwise, return -1.
      */
     fn index_of_delimiter(c: char) -> isize {
-        let mut left = 0;
-        let mut right = DELIMITERS.len() - 1;
-        while left <= right {
-            let mid = (left + right) / 2;
-            match DELIMITERS[mid].cmp(&(c as u8)) {
-                std::cmp::Ordering::Equal => return mid as isize,
-                std::cmp::Ordering::Less => left = mid + 1,
-                std::cmp::Ordering::Greater => right = mid - 1,
-            }
+        // Most chars are not delimiters, so avoid the binary search when possible
+        if !Self::is_delimiter_fast(c) {
+            return -1;
+        }
+        Self::search_delimiters(c)
+    }
+
+    /*
+     * Pre-condition: `c` is ASCII.
+     *
+     * Return 0 through 26 if delimiter is found by binary search. Otherwise, return -1.
+     */
+    fn search_delimiters(c: char) -> isize {
+        match DELIMITERS.binary_search(&(c as u8)) {
+            Ok(index) => index as isize,
+            Err(_) => -1,
         }
-        -1
     }
 
     fn is_delimiter(c: char) -> bool {
-        Self::index_of_delimiter(c) > -1
+        Self::is_delimiter_fast(c)
+    }
+
+    fn is_delimiter_fast(c: char) -> bool {
+        if c as u32 > 127 {
+            return false;
+        }
+        (DELIMITER_MASK >> c as u32) & 1 == 1
     }
 
     fn is_digit(c: char) -> bool {
@@ -494,6 +889,23 @@ impl<'a> LexerIter<'a> {
         }
     }
 
+    /*
+     * Pre-condition:
+     *   `current` and `current_plus_1` are the "<<" chars that may begin a heredoc.
+     */
+    fn is_heredoc_start(&self) -> bool {
+        let tag_start = self.current.unwrap().byte_index as usize + 2;
+        match self.source[tag_start..].chars().next() {
+            Some(c) => !Self::is_digit(c) && Self::is_keyword_or_ident_char(c),
+            None => false,
+        }
+    }
+
+    fn is_weak_keyword(&self, start: usize, stop: usize) -> bool {
+        let value = &self.source[start..=stop];
+        value == AS_VALUE || value == ASK_VALUE || value == HANDLE_VALUE || value == TELL_VALUE
+    }
+
     fn is_keyword_or_ident_char(c: char) -> bool {
         c >= '0' && c <= '9' || c >= 'a' && c <= 'z' || c >= 'A' && c <= 'Z' || c == '_'
     }
@@ -506,6 +918,14 @@ impl<'a> LexerIter<'a> {
         Self::is_whitespace(c) || Self::is_delimiter(c)
     }
 
+    fn is_some_ident_char(index: Option<LexerIndex>) -> bool {
+        index.is_some() && Self::is_keyword_or_ident_char(index.unwrap().char)
+    }
+
+    fn is_some_run_digit(index: Option<LexerIndex>, is_run_digit: fn(char) -> bool) -> bool {
+        index.is_some() && is_run_digit(index.unwrap().char)
+    }
+
     fn is_some_char(index: Option<LexerIndex>, c: char) -> bool {
         if index.is_none() {
             return false;
@@ -571,7 +991,41 @@ impl<'a> LexerIter<'a> {
         }
     }
 
-    fn new(source: &'a str) -> Self {
+    /// Fill `buf` with the remaining tokens, excluding the EOF token, and return the token count.
+    /// The buffer is cleared first so that its allocation can be reused across calls. On error,
+    /// the buffer is cleared again before returning so it is never left partially filled.
+    pub fn tokenize_into(&mut self, buf: &mut Vec<Token<'a>>) -> Result<usize, LexerError> {
+        buf.clear();
+        loop {
+            match self.next() {
+                Ok(token) if token.token_type == TokenType::Eof => return Ok(buf.len()),
+                Ok(token) => buf.push(token),
+                Err(err) => {
+                    buf.clear();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Collect the remaining tokens, excluding the EOF token, into a freshly allocated vector.
+    pub fn into_vec(mut self) -> Result<Vec<Token<'a>>, LexerError> {
+        let mut buf = Vec::new();
+        self.tokenize_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Number of source bytes before the current char. Does not advance the lexer.
+    pub fn bytes_consumed(&self) -> usize {
+        self.source.len() - self.source_remaining().len()
+    }
+
+    /// Source text not yet lexed, starting at the current char. Does not advance the lexer.
+    pub fn source_remaining(&self) -> &'a str {
+        &self.source[self.current_byte_index()..]
+    }
+
+    pub fn new(source: &'a str) -> Self {
         let mut str_iter = source.chars();
         let current = Self::fetch_next_char(&mut str_iter, &None);
         LexerIter {
@@ -579,9 +1033,32 @@ impl<'a> LexerIter<'a> {
             str_iter,
             current,
             current_plus_1: None,
+            eof_returned: false,
+            peeked: None,
+        }
+    }
+
+    /*
+     * Post-condition:
+     *   The peeked result, if any, has been consumed and cleared.
+     */
+    fn next(&mut self) -> Result<Token<'a>, LexerError> {
+        match self.peeked.take() {
+            Some(result) => result,
+            None => self.lex_next(),
         }
     }
 
+    /// Return the next token or error without consuming it. The same result is returned by every
+    /// `peek` until `next` consumes it. Peeking lexes ahead, so `bytes_consumed` and
+    /// `source_remaining` reflect the position after the peeked token.
+    pub fn peek(&mut self) -> Result<&Token<'a>, &LexerError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex_next());
+        }
+        self.peeked.as_ref().unwrap().as_ref()
+    }
+
     /*
      * Pre-condition:
      *   `current` is EOF, a separator, or the first char of the next token to parse.
@@ -589,7 +1066,7 @@ impl<'a> LexerIter<'a> {
      * Post-condition:
      *   `current` is EOF, a separator, or the first char of the next token to parse.
      */
-    fn next(&mut self) -> Result<Token, LexerError> {
+    fn lex_next(&mut self) -> Result<Token<'a>, LexerError> {
         self.skip_whitespace();
         if self.current.is_none() {
             return Ok(EOF_TOKEN);
@@ -601,6 +1078,13 @@ impl<'a> LexerIter<'a> {
         if current.char == '\'' {
             return self.parse_single_quoted_str();
         }
+        if current.char == '$' && Self::is_some_char(self.peek_1(), '\'') {
+            return self.parse_char_literal();
+        }
+        if current.char == '<' && Self::is_some_char(self.peek_1(), '<') && self.is_heredoc_start()
+        {
+            return self.parse_heredoc();
+        }
         if current.char == '"' {
             return self.parse_double_quoted_str();
         }
@@ -641,6 +1125,109 @@ impl<'a> LexerIter<'a> {
         }
     }
 
+    /*
+     * Pre-condition:
+     *   `start` is the first digit of a number.
+     *   `current` is a digit, or a prefix char such as "x" followed by a digit.
+     *
+     * Post-condition:
+     *   `current` is the last digit of the run. Every "_" separator accepted is between two
+     *   digits.
+     */
+    fn accept_digit_run(
+        &mut self,
+        start: LexerIndex,
+        is_run_digit: fn(char) -> bool,
+    ) -> Result<(), LexerError> {
+        loop {
+            if Self::is_some_run_digit(self.peek_1(), is_run_digit) {
+                self.next_char();
+            } else if Self::is_some_char(self.peek_1(), '_') {
+                // Make "_" `current`, which must be followed by another digit
+                self.next_char();
+                if !Self::is_some_run_digit(self.peek_1(), is_run_digit) {
+                    return Err(LexerError {
+                        message: MISPLACED_DIGIT_SEPARATOR,
+                        index: start,
+                    });
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /*
+     * Pre-condition:
+     *   `current` is the "0" char of a binary or octal number.
+     *   `current_plus_1` is the radix char "b", "B", "o", or "O".
+     *
+     * Post-condition:
+     *   `current` is the last digit of the number.
+     */
+    fn accept_radix_digits(
+        &mut self,
+        is_radix_digit: fn(char) -> bool,
+        message: &'static str,
+    ) -> Result<(), LexerError> {
+        let start = self.current.unwrap();
+        // Make the radix char `current`
+        self.next_char();
+        if Self::is_some_char(self.peek_1(), '_') {
+            return Err(LexerError {
+                message: MISPLACED_DIGIT_SEPARATOR,
+                index: start,
+            });
+        }
+        // At least one digit must follow the prefix
+        if !Self::is_some_run_digit(self.peek_1(), is_radix_digit) {
+            return Err(LexerError {
+                message,
+                index: start,
+            });
+        }
+        self.accept_digit_run(start, is_radix_digit)?;
+        // A decimal digit outside the radix is not a suffix
+        if Self::is_some_digit(self.peek_1()) {
+            return Err(LexerError {
+                message,
+                index: start,
+            });
+        }
+        Ok(())
+    }
+
+    /*
+     * Pre-condition:
+     *   `backslash` is the escape char inside a string.
+     *   `current` is the char (or EOF) following the backslash.
+     *
+     * Post-condition:
+     *   `current` is the char (or EOF) following the escape sequence.
+     */
+    fn accept_str_escape(&mut self, backslash: LexerIndex) -> Result<(), LexerError> {
+        let digit_count = match self.current {
+            Some(index) if index.char == 'u' => 4,
+            Some(index) if index.char == 'U' => 8,
+            _ => {
+                self.next_char();
+                return Ok(());
+            }
+        };
+        // Accept "u" or "U"
+        self.next_char();
+        for _ in 0..digit_count {
+            if !Self::is_some_run_digit(self.current, |c| c.is_ascii_hexdigit()) {
+                return Err(LexerError {
+                    message: INVALID_UNICODE_ESCAPE,
+                    index: backslash,
+                });
+            }
+            self.next_char();
+        }
+        Ok(())
+    }
+
     /*
      * Pre-condition:
      *   `current` is the "/" char of a block comment start.
@@ -679,6 +1266,49 @@ impl<'a> LexerIter<'a> {
         Ok(self.make_token(start, stop, TokenType::Comment))
     }
 
+    /*
+     * Pre-condition:
+     *   `current` is the "$" char of a char literal.
+     *   `current_plus_1` is the opening single quote char.
+     *
+     * Post-condition:
+     *   `current` is the next char (or EOF) after the closing single quote char.
+     */
+    fn parse_char_literal(&mut self) -> Result<Token<'a>, LexerError> {
+        let start = self.current.unwrap();
+        // Accept "$" and make the opening quote `current`
+        self.next_char();
+        // Accept the opening quote
+        self.next_char();
+        // Accept content up to the closing quote so that `$'ab'` is reported as one bad literal
+        while self.current.is_some() && self.current.unwrap().char != '\'' {
+            let c = self.current.unwrap().char;
+            self.next_char();
+            // Accept escaped character
+            if c == '\\' && self.current.is_some() {
+                self.next_char();
+            }
+        }
+        if self.current.is_none() {
+            return Err(LexerError {
+                message: INVALID_CHAR_LITERAL,
+                index: start,
+            });
+        }
+        let stop = self.current.unwrap();
+        let content_start = start.byte_index as usize + 2;
+        let content_stop = stop.byte_index as usize;
+        if decode_char_literal(&self.source[content_start..content_stop]).is_none() {
+            return Err(LexerError {
+                message: INVALID_CHAR_LITERAL,
+                index: start,
+            });
+        }
+        // Ensure that we have met our post-condition
+        self.next_char();
+        Ok(self.make_token(start, stop, TokenType::Char))
+    }
+
     /*
      * Pre-condition:
      *   `current` is the first double quote char.
@@ -698,11 +1328,11 @@ impl<'a> LexerIter<'a> {
             index.unwrap().char != '"'
         }
         while is_str_content(self.current) {
-            let c = self.current().unwrap().char;
+            let index = self.current().unwrap();
             self.next_char();
             // Accept escaped character
-            if c == '\\' {
-                self.next_char();
+            if index.char == '\\' {
+                self.accept_str_escape(index)?;
             }
         }
         if self.current.is_none() {
@@ -717,6 +1347,49 @@ impl<'a> LexerIter<'a> {
         Ok(self.make_token(start, stop, TokenType::Str))
     }
 
+    /*
+     * Pre-condition:
+     *   `current` is the first "<" char of a heredoc start "<<TAG".
+     *
+     * Post-condition:
+     *   `current` is the newline char (or EOF) after the closing tag.
+     */
+    fn parse_heredoc(&mut self) -> Result<Token<'a>, LexerError> {
+        let start = self.current.unwrap();
+        // Accept both "<" chars, making the first tag char `current`
+        self.next_char();
+        self.next_char();
+        let tag_start = self.current.unwrap().byte_index as usize;
+        while Self::is_some_ident_char(self.current) {
+            self.next_char();
+        }
+        let tag = &self.source[tag_start..self.current_byte_index()];
+        // Skip whatever follows the tag on the opening line
+        while self.current.is_some() && self.current.unwrap().char != '\n' {
+            self.next_char();
+        }
+        loop {
+            if self.current.is_none() {
+                return Err(LexerError {
+                    message: STR_IS_MISSING_CLOSING_TAG,
+                    index: start,
+                });
+            }
+            // Accept the newline that ends the previous line
+            self.next_char();
+            let line_start = self.current_byte_index();
+            let mut last = None;
+            while self.current.is_some() && self.current.unwrap().char != '\n' {
+                last = self.current;
+                self.next_char();
+            }
+            if &self.source[line_start..self.current_byte_index()] == tag {
+                // The tag is not empty, so the closing line has a last char
+                return Ok(self.make_token(start, last.unwrap(), TokenType::Str));
+            }
+        }
+    }
+
     /*
      * Pre-condition:
      *   `start` is the first digit of a number.
@@ -754,16 +1427,12 @@ impl<'a> LexerIter<'a> {
             });
         }
         // Accept `current` digit while `current_plus_1` is a digit
-        while Self::is_some_digit(self.peek_1()) {
-            self.next_char();
-        }
+        self.accept_digit_run(start, Self::is_digit)?;
         let last_exponent_digit = self.current.unwrap();
         // Return a token if certain the optional suffix is not present
         if Self::is_eof_or_separator(self.peek_1()) {
-            // Accept `current` if next char is a separator
-            if self.current_plus_1.is_some() {
-                self.next_char();
-            }
+            // Accept `current`, making the separator or EOF the new `current`
+            self.next_char();
             return Ok(self.make_token(start, last_exponent_digit, TokenType::Flt));
         }
         // Load the optional suffix if present
@@ -795,16 +1464,12 @@ impl<'a> LexerIter<'a> {
      *   `current` is EOF or a separator (whitespace or delimiter).
      */
     fn parse_fractional_part(&mut self, start: LexerIndex) -> Result<Token<'a>, LexerError> {
-        while Self::is_some_digit(self.peek_1()) {
-            self.next_char();
-        }
+        self.accept_digit_run(start, Self::is_digit)?;
         let last_fractional_digit = self.current.unwrap();
         // Return a token if the optional exponent or suffix is not present
         if Self::is_eof_or_separator(self.peek_1()) {
-            // Accept `current` if next char is a separator
-            if self.current_plus_1.is_some() {
-                self.next_char();
-            }
+            // Accept `current`, making the separator or EOF the new `current`
+            self.next_char();
             return Ok(self.make_token(start, last_fractional_digit, TokenType::Flt));
         }
         // 'current_plus_1' is loaded because we peeked above
@@ -861,8 +1526,11 @@ impl<'a> LexerIter<'a> {
         let stop = self.current.unwrap();
         // Ensure that we have met our post-condition
         self.next_char();
-        if self.is_keyword(start.byte_index as usize, stop.byte_index as usize) {
+        let (start_byte, stop_byte) = (start.byte_index as usize, stop.byte_index as usize);
+        if self.is_keyword(start_byte, stop_byte) {
             Ok(self.make_token(start, stop, TokenType::Keyword))
+        } else if self.is_weak_keyword(start_byte, stop_byte) {
+            Ok(self.make_token(start, stop, TokenType::WeakKeyword))
         } else {
             Ok(self.make_token(start, stop, TokenType::Ident))
         }
@@ -916,25 +1584,23 @@ impl<'a> LexerIter<'a> {
                         index: start,
                     });
                 }
+                if Self::is_some_char(self.peek_1(), '_') {
+                    return Err(LexerError {
+                        message: MISPLACED_DIGIT_SEPARATOR,
+                        index: start,
+                    });
+                }
                 // Accept first hex digit
                 self.next_char();
                 // Accept remaining hex characters
-                fn is_hex_content(index: Option<LexerIndex>) -> bool {
-                    if index.is_none() {
-                        return false;
-                    }
-                    let c = index.unwrap().char;
-                    c >= '0' && c <= '9' || c >= 'a' && c <= 'f' || c >= 'A' && c <= 'F'
-                }
-                while is_hex_content(self.peek_1()) {
-                    self.next_char();
-                }
+                self.accept_digit_run(start, |c| c.is_ascii_hexdigit())?;
+            } else if p1 == 'b' || p1 == 'B' {
+                self.accept_radix_digits(|c| c == '0' || c == '1', INVALID_BINARY_NUMBER)?;
+            } else if p1 == 'o' || p1 == 'O' {
+                self.accept_radix_digits(|c| ('0'..='7').contains(&c), INVALID_OCTAL_NUMBER)?;
             }
         } else {
-            while Self::is_some_digit(self.peek_1()) {
-                // Make the next digit `current`
-                self.next_char();
-            }
+            self.accept_digit_run(start, Self::is_digit)?;
         }
         // `current` is now the last digit of the whole number
         if Self::is_some_char(self.peek_1(), '.') {
@@ -953,11 +1619,9 @@ impl<'a> LexerIter<'a> {
         } else {
             if Self::is_eof_or_separator(self.peek_1()) {
                 let stop = self.current.unwrap();
-                let token = self.make_token(start, stop, TokenType::Int);
-                if self.current_plus_1.is_some() {
-                    self.next_char();
-                }
-                Ok(token)
+                // Accept `current`, making the separator or EOF the new `current`
+                self.next_char();
+                Ok(self.make_token(start, stop, TokenType::Int))
             } else {
                 // We have a possible suffix (not EOF or a separator).
                 // We have `current_plus_1` loaded because we peeked successfully above.
@@ -1043,11 +1707,11 @@ impl<'a> LexerIter<'a> {
             index.unwrap().char != '\''
         }
         while is_str_content(self.current) {
-            let c = self.current().unwrap().char;
+            let index = self.current().unwrap();
             self.next_char();
             // Accept escaped character
-            if c == '\\' {
-                self.next_char();
+            if index.char == '\\' {
+                self.accept_str_escape(index)?;
             }
         }
         if self.current.is_none() {
@@ -1118,3 +1782,263 @@ impl<'a> LexerIter<'a> {
         }
     }
 }
+
+/// Yield each token followed by the EOF token exactly once, then `None`. After an error, the
+/// remaining source is abandoned and the next item is the EOF token.
+impl<'a> Iterator for LexerIter<'a> {
+    type Item = Result<Token<'a>, LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.eof_returned {
+            return None;
+        }
+        let result = LexerIter::next(self);
+        match &result {
+            Ok(token) if token.token_type == TokenType::Eof => self.eof_returned = true,
+            Err(_) => {
+                self.current = None;
+                self.current_plus_1 = None;
+            }
+            _ => {}
+        }
+        Some(result)
+    }
+}
+
+/// Lex all of `source`, stopping at the first error. On success, the last token is always the EOF
+/// token.
+pub fn lex_all(source: &str) -> Result<Vec<Token<'_>>, LexerError> {
+    LexerIter::new(source).collect()
+}
+
+/// Same as [`lex_all`], but without comment tokens.
+pub fn lex_skip_comments(source: &str) -> Result<Vec<Token<'_>>, LexerError> {
+    LexerIter::new(source)
+        .filter(|r| !matches!(r, Ok(token) if token.token_type == TokenType::Comment))
+        .collect()
+}
+
+/// Lex `source` into `buf`, reusing its allocation. See [`LexerIter::tokenize_into`].
+pub fn tokenize_into<'a>(source: &'a str, buf: &mut Vec<Token<'a>>) -> Result<usize, LexerError> {
+    LexerIter::new(source).tokenize_into(buf)
+}
+
+/// Return the token whose bytes contain `byte`. If `byte` falls between tokens, return the next
+/// token. If `byte` is at or past the end of the last token, return the EOF token.
+///
+/// Tokens are scanned from the start of `source`, so this is linear in the position of `byte`.
+pub fn token_at_byte(source: &str, byte: u32) -> Result<Token<'_>, LexerError> {
+    let mut lexer_iter = LexerIter::new(source);
+    loop {
+        let token = lexer_iter.next()?;
+        if token.token_type == TokenType::Eof
+            || byte < token.byte_index as u32 + token.value.len() as u32
+        {
+            return Ok(token);
+        }
+    }
+}
+
+/// Delimiter checks for the `delimiter_lookup` bench, which compares a binary search of the
+/// lexer's delimiter table with the bit mask that the lexer checks first. Not part of the public
+/// API.
+#[doc(hidden)]
+pub mod delimiter_lookup {
+    use super::LexerIter;
+
+    pub fn is_delimiter_binary_search(c: char) -> bool {
+        c.is_ascii() && LexerIter::search_delimiters(c) >= 0
+    }
+
+    pub fn is_delimiter_mask(c: char) -> bool {
+        LexerIter::is_delimiter_fast(c)
+    }
+}
+
+/// A set of filters applied by a [`Tokenizer`]. Modes combine with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenizerMode(u8);
+
+impl TokenizerMode {
+    pub const NONE: TokenizerMode = TokenizerMode(0);
+    pub const SKIP_COMMENTS: TokenizerMode = TokenizerMode(1);
+    /// Reserved for when the lexer produces whitespace tokens. It has no effect today.
+    pub const SKIP_WHITESPACE_TOKENS: TokenizerMode = TokenizerMode(1 << 1);
+    /// Keep only keywords and weak keywords. A weak keyword may turn out to be an identifier, but
+    /// a highlighter treats it as a keyword like the lexer does.
+    pub const ONLY_KEYWORDS: TokenizerMode = TokenizerMode(1 << 2);
+
+    pub fn contains(self, other: TokenizerMode) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for TokenizerMode {
+    type Output = TokenizerMode;
+
+    fn bitor(self, rhs: TokenizerMode) -> TokenizerMode {
+        TokenizerMode(self.0 | rhs.0)
+    }
+}
+
+/// An iterator over a filtered view of the tokens in a source. Iteration ends before the EOF
+/// token, or after the first error.
+pub struct Tokenizer<'a> {
+    inner: LexerIter<'a>,
+    mode: TokenizerMode,
+    done: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Create a tokenizer that returns every token.
+    pub fn new(source: &'a str) -> Self {
+        Self::with_mode(source, TokenizerMode::NONE)
+    }
+
+    /// Create a tokenizer that omits comments, which the parser does not need.
+    pub fn for_parser(source: &'a str) -> Self {
+        Self::with_mode(source, TokenizerMode::SKIP_COMMENTS)
+    }
+
+    /// Create a tokenizer that keeps comments (and, in the future, whitespace) for highlighting.
+    pub fn for_syntax_highlight(source: &'a str) -> Self {
+        Self::with_mode(source, TokenizerMode::NONE)
+    }
+
+    pub fn with_mode(source: &'a str, mode: TokenizerMode) -> Self {
+        Tokenizer {
+            inner: LexerIter::new(source),
+            mode,
+            done: false,
+        }
+    }
+
+    pub fn mode(&self) -> TokenizerMode {
+        self.mode
+    }
+
+    fn is_included(&self, token: &Token) -> bool {
+        if self.mode.contains(TokenizerMode::SKIP_COMMENTS)
+            && token.token_type == TokenType::Comment
+        {
+            return false;
+        }
+        if self.mode.contains(TokenizerMode::ONLY_KEYWORDS)
+            && token.token_type != TokenType::Keyword
+            && token.token_type != TokenType::WeakKeyword
+        {
+            return false;
+        }
+        true
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>, LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.inner.next() {
+                Ok(token) if token.token_type == TokenType::Eof => self.done = true,
+                Ok(token) => {
+                    if self.is_included(&token) {
+                        return Some(Ok(token));
+                    }
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Error returned by [`TokenStream::expect`] when the next token is not of the expected type.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub expected: TokenType,
+    pub found: TokenType,
+    pub byte_index: i32,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected {:?} but found {:?} at byte {}",
+            self.expected, self.found, self.byte_index
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A lexed token vector with a cursor, for parsers that need look-ahead and backtracking.
+///
+/// The stream always ends with an EOF token. Reading past the end answers that EOF token again
+/// rather than panicking.
+pub struct TokenStream<'a> {
+    tokens: Vec<Token<'a>>,
+    cursor: usize,
+}
+
+impl<'a> TokenStream<'a> {
+    /// Create a stream over `tokens`, appending an EOF token if the last token is not EOF.
+    pub fn new(mut tokens: Vec<Token<'a>>) -> Self {
+        if tokens.last().map(|t| t.token_type) != Some(TokenType::Eof) {
+            tokens.push(EOF_TOKEN);
+        }
+        TokenStream { tokens, cursor: 0 }
+    }
+
+    /// Return the token at the cursor and move the cursor past it.
+    pub fn advance(&mut self) -> &Token<'a> {
+        let index = self.cursor;
+        if !self.is_at_end() {
+            self.cursor += 1;
+        }
+        &self.tokens[index]
+    }
+
+    /// Advance past the token at the cursor if it has type `token_type`. Otherwise, return an
+    /// error and leave the cursor unchanged.
+    pub fn expect(&mut self, token_type: TokenType) -> Result<&Token<'a>, ParseError> {
+        let found = self.peek();
+        if found.token_type != token_type {
+            return Err(ParseError {
+                expected: token_type,
+                found: found.token_type,
+                byte_index: found.byte_index,
+            });
+        }
+        Ok(self.advance())
+    }
+
+    /// True if the cursor is at the trailing EOF token.
+    pub fn is_at_end(&self) -> bool {
+        self.cursor == self.tokens.len() - 1
+    }
+
+    /// Return the token at the cursor without advancing.
+    pub fn peek(&self) -> &Token<'a> {
+        self.peek_n(0)
+    }
+
+    /// Return the token `n` positions past the cursor without advancing.
+    pub fn peek_n(&self, n: usize) -> &Token<'a> {
+        let index = self.cursor.saturating_add(n).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    /// Move the cursor back to a position previously returned by [`TokenStream::save`].
+    pub fn restore(&mut self, position: usize) {
+        self.cursor = position.min(self.tokens.len() - 1);
+    }
+
+    /// Return the cursor position, for a later [`TokenStream::restore`].
+    pub fn save(&self) -> usize {
+        self.cursor
+    }
+}
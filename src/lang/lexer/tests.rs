@@ -1,4 +1,24 @@
 use super::*;
+use crate::lang::diagnostic::render_diagnostic;
+
+const FACTORIAL: &str = r#"
+        /*
+         * The classic factorial function in a
+         * continuation-passing style.
+         */
+        actor Factorial() in
+            func fact(x) in
+                // Use continuation-passing style
+                func fact_cps(n, k) in
+                    if n < 2 then k
+                    else fact_cps(n - 1, n * k) end
+                end
+                fact_cps(x, 1)
+            end
+            handle ask x in
+                fact(x)
+            end
+        end"#;
 
 fn assert_parse_eq(source: &str) {
     let mut lexer_iter = LexerIter::new(source);
@@ -66,6 +86,40 @@ fn test_adjacent_two_char_symbols() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_binary_int() {
+    for source in [
+        "0b0",
+        "0b1",
+        "0b1010",
+        "0B0",
+        "0B11111111",
+        "0b1010l",
+        "0b1L",
+    ] {
+        let mut lexer_iter = LexerIter::new(source);
+        let n = lexer_iter.next().unwrap();
+        assert_eq!(source, n.value);
+        assert_eq!(0, n.byte_index);
+        assert_eq!(TokenType::Int, n.token_type);
+        assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    }
+    let tokens = lex_all("x+0b11;").unwrap();
+    assert_eq!("0b11", tokens[2].value);
+    assert_eq!(";", tokens[3].value);
+}
+
+#[test]
+fn test_binary_int_errors() {
+    for source in ["0b", "0b2", "0b102", "0b ", "0B;", "0bl"] {
+        let err = LexerIter::new(source).next().unwrap_err();
+        assert_eq!(INVALID_BINARY_NUMBER, err.message());
+        assert_eq!(0, err.byte_offset());
+    }
+    let err = LexerIter::new("0b1q").next().unwrap_err();
+    assert_eq!(INTEGER_SUFFIX_MUST_BE_ONE_OF, err.message());
+}
+
 #[test]
 fn test_block_comment() {
     let source = "/**/";
@@ -95,6 +149,138 @@ fn test_block_comment() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_byte_index_to_char_index() {
+    // See `test_iter_chars` for the byte and char offsets of this source
+    let source = "1+🦀+2";
+    assert_eq!(Some(0), LexerIter::byte_index_to_char_index(source, 0));
+    assert_eq!(Some(1), LexerIter::byte_index_to_char_index(source, 1));
+    assert_eq!(Some(2), LexerIter::byte_index_to_char_index(source, 2));
+    assert_eq!(Some(3), LexerIter::byte_index_to_char_index(source, 6));
+    assert_eq!(Some(4), LexerIter::byte_index_to_char_index(source, 7));
+    assert_eq!(Some(5), LexerIter::byte_index_to_char_index(source, 8));
+    // Inside the crab
+    assert_eq!(None, LexerIter::byte_index_to_char_index(source, 3));
+    assert_eq!(None, LexerIter::byte_index_to_char_index(source, 5));
+    // Out of range
+    assert_eq!(None, LexerIter::byte_index_to_char_index(source, 9));
+}
+
+#[test]
+fn test_byte_range_and_char_len() {
+    let source = "x = 'h\u{e9}llo' + abc";
+    let tokens = lex_all(source).unwrap();
+    assert_eq!(0..1, tokens[0].byte_range());
+    assert_eq!(4..12, tokens[2].byte_range());
+    assert_eq!(7, tokens[2].char_len());
+    assert_eq!(13..14, tokens[3].byte_range());
+    for token in &tokens[..tokens.len() - 1] {
+        assert_eq!(token.value, &source[token.byte_range()]);
+        assert_eq!(token.value.len(), token.byte_range().len());
+    }
+    assert_eq!(0..0, EOF_TOKEN.byte_range());
+    assert_eq!(3, EOF_TOKEN.char_len());
+}
+
+#[test]
+fn test_char_index_to_byte_index() {
+    // See `test_iter_chars` for the byte and char offsets of this source
+    let source = "1+🦀+2";
+    assert_eq!(Some(0), LexerIter::char_index_to_byte_index(source, 0));
+    assert_eq!(Some(1), LexerIter::char_index_to_byte_index(source, 1));
+    assert_eq!(Some(2), LexerIter::char_index_to_byte_index(source, 2));
+    assert_eq!(Some(6), LexerIter::char_index_to_byte_index(source, 3));
+    assert_eq!(Some(7), LexerIter::char_index_to_byte_index(source, 4));
+    assert_eq!(Some(8), LexerIter::char_index_to_byte_index(source, 5));
+    assert_eq!(None, LexerIter::char_index_to_byte_index(source, 6));
+    assert_eq!(Some(0), LexerIter::char_index_to_byte_index("", 0));
+    assert_eq!(None, LexerIter::char_index_to_byte_index("", 1));
+}
+
+#[test]
+fn test_char_literal() {
+    let cases = [
+        (r"$'a'", 'a'),
+        (r"$'\n'", '\n'),
+        (r"$'\t'", '\t'),
+        (r"$'\\'", '\\'),
+        (r"$'\''", '\''),
+        (r"$'\u{41}'", 'A'),
+        (r"$'\u{1F980}'", '🦀'),
+        (r"$'🦀'", '🦀'),
+        (r"$'$'", '$'),
+    ];
+    for (source, expected) in cases {
+        let mut lexer_iter = LexerIter::new(source);
+        let token = lexer_iter.next().unwrap();
+        assert_eq!(source, token.value);
+        assert_eq!(0, token.byte_index);
+        assert_eq!(TokenType::Char, token.token_type);
+        assert_eq!(expected, token.char_value(source).unwrap());
+        assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    }
+    let source = "c == $'x'";
+    let tokens = LexerIter::new(source).into_vec().unwrap();
+    assert_eq!(3, tokens.len());
+    assert_eq!("$'x'", tokens[2].value);
+    assert_eq!(5, tokens[2].byte_index);
+    assert_eq!(TokenType::Char, tokens[2].token_type);
+    assert_eq!('x', tokens[2].char_value(source).unwrap());
+}
+
+#[test]
+fn test_char_literal_errors() {
+    let sources = [
+        r"$''",
+        r"$'ab'",
+        r"$'a",
+        r"$'",
+        r"$'\q'",
+        r"$'\u{}'",
+        r"$'\u{D800}'",
+        r"$'\u{110000}'",
+        r"$'\u{1234567}'",
+        r"$'\u41'",
+    ];
+    for source in sources {
+        let mut lexer_iter = LexerIter::new(source);
+        let err = lexer_iter.next().unwrap_err();
+        assert_eq!(INVALID_CHAR_LITERAL, err.message);
+        assert_eq!(0, err.byte_offset());
+    }
+    // A dollar sign not followed by a quote is not a char literal
+    let err = LexerIter::new("$a").next().unwrap_err();
+    assert_eq!(UNRECOGNIZED_TOKEN, err.message);
+    // Only char tokens have a char value
+    let token = LexerIter::new("'a'").next().unwrap();
+    let err = token.char_value("'a'").unwrap_err();
+    assert_eq!(INVALID_CHAR_LITERAL, err.message);
+    assert_eq!(1, err.line());
+    assert_eq!(1, err.column());
+    // The error is located in the source the token was read from
+    let source = "x = 1\n  y = 'é'";
+    let tokens = LexerIter::new(source).into_vec().unwrap();
+    let err = tokens[5].char_value(source).unwrap_err();
+    assert_eq!(INVALID_CHAR_LITERAL, err.message);
+    assert_eq!(2, err.line());
+    assert_eq!(7, err.column());
+    assert_eq!(12, err.byte_offset());
+}
+
+#[test]
+fn test_delimiter_mask_matches_delimiters() {
+    for c in 0..=255u8 {
+        let c = c as char;
+        let expected = c.is_ascii() && DELIMITERS.contains(&(c as u8));
+        assert_eq!(expected, LexerIter::is_delimiter_fast(c));
+        assert_eq!(expected, delimiter_lookup::is_delimiter_binary_search(c));
+        assert_eq!(expected, delimiter_lookup::is_delimiter_mask(c));
+    }
+    for (i, b) in DELIMITERS.iter().enumerate() {
+        assert_eq!(i as isize, LexerIter::index_of_delimiter(*b as char));
+    }
+}
+
 #[test]
 fn test_delimiters() {
     // All 27 delimiters:
@@ -128,6 +314,51 @@ fn test_delimiters() {
     // Not a delimiter
     assert_eq!(false, LexerIter::is_delimiter('?'));
     assert_eq!(false, LexerIter::is_delimiter('^'));
+    // Not a delimiter even though the low byte of the code point is '(' (0x28)
+    assert_eq!(false, LexerIter::is_delimiter('\u{0128}'));
+    assert_eq!(-1, LexerIter::index_of_delimiter('\u{0128}'));
+    // Below the first delimiter
+    assert_eq!(-1, LexerIter::index_of_delimiter('\0'));
+}
+
+#[test]
+fn test_digit_separators() {
+    let cases = [
+        ("1_000_000", TokenType::Int),
+        ("1_0l", TokenType::Int),
+        ("1_0m", TokenType::Dec),
+        ("0xFF_AA_BB", TokenType::Int),
+        ("0b1010_1010", TokenType::Int),
+        ("0o7_7_7", TokenType::Int),
+        ("3.141_592_653", TokenType::Flt),
+        ("1_000.0e+3", TokenType::Flt),
+        ("1.0e1_0", TokenType::Flt),
+        ("1_0.5_5f", TokenType::Flt),
+    ];
+    for (source, token_type) in cases {
+        let mut lexer_iter = LexerIter::new(source);
+        let n = lexer_iter.next().unwrap();
+        // The token value keeps the underscores
+        assert_eq!(source, n.value);
+        assert_eq!(token_type, n.token_type);
+        assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    }
+}
+
+#[test]
+fn test_digit_separator_errors() {
+    let sources = [
+        "1_", "1__0", "1_.0", "1_l", "1_m", "1.0_", "1.0_e3", "1.0e3_", "1.0_f", "0x_FF", "0xFF_",
+        "0b_1", "0b1_", "0o_7", "0o7_", "1_ ", "1_;",
+    ];
+    for source in sources {
+        let err = LexerIter::new(source).next().unwrap_err();
+        assert_eq!(MISPLACED_DIGIT_SEPARATOR, err.message(), "{}", source);
+        assert_eq!(0, err.byte_offset());
+    }
+    // An underscore may not start a number, so this is an identifier
+    let token = LexerIter::new("_1").next().unwrap();
+    assert_eq!(TokenType::Ident, token.token_type);
 }
 
 #[test]
@@ -168,6 +399,56 @@ fn test_double_quoted_str() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_heredoc() {
+    let source = "var q = <<SQL\nSELECT *\n  FROM t\nWHERE a = 'x' AND b = \"y\"\nSQL\nq";
+    let mut lexer_iter = LexerIter::new(source);
+    assert_eq!("var", lexer_iter.next().unwrap().value);
+    assert_eq!("q", lexer_iter.next().unwrap().value);
+    assert_eq!("=", lexer_iter.next().unwrap().value);
+    let token = lexer_iter.next().unwrap();
+    assert_eq!(TokenType::Str, token.token_type);
+    assert_eq!(8, token.byte_index);
+    assert_eq!(
+        "<<SQL\nSELECT *\n  FROM t\nWHERE a = 'x' AND b = \"y\"\nSQL",
+        token.value
+    );
+    let body = token.heredoc_body().unwrap();
+    assert_eq!("SELECT *\n  FROM t\nWHERE a = 'x' AND b = \"y\"", body);
+    assert_eq!(false, body.contains("SQL"));
+    assert_eq!("q", lexer_iter.next().unwrap().value);
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    // The closing tag must be alone on its line, and may end the source
+    let source = "<<END\n END\nEND;\nEND";
+    let token = LexerIter::new(source).next().unwrap();
+    assert_eq!(source, token.value);
+    assert_eq!(Some(" END\nEND;"), token.heredoc_body());
+    let token = LexerIter::new("<<E\nE").next().unwrap();
+    assert_eq!(Some(""), token.heredoc_body());
+    assert_eq!(None, LexerIter::new("'E'").next().unwrap().heredoc_body());
+    // Without a tag, "<<" is two symbols
+    let tokens = LexerIter::new("a<<1;").into_vec().unwrap();
+    assert_eq!(5, tokens.len());
+    assert_eq!(TokenType::OneCharSym, tokens[1].token_type);
+}
+
+#[test]
+fn test_heredoc_missing_closing_tag() {
+    for source in [
+        "<<SQL",
+        "<<SQL\nSELECT 1",
+        "<<SQL\nSELECT 1\nSQL2\n",
+        "x <<SQL\n SQL",
+    ] {
+        let err = LexerIter::new(source).into_vec().unwrap_err();
+        assert_eq!(STR_IS_MISSING_CLOSING_TAG, err.message());
+    }
+    let err = LexerIter::new("x\n  <<SQL\n").into_vec().unwrap_err();
+    assert_eq!(4, err.byte_offset());
+    assert_eq!(2, err.line());
+    assert_eq!(3, err.column());
+}
+
 #[test]
 fn test_hex_int_with_small_x() {
     let source = "0xAE";
@@ -200,6 +481,20 @@ fn test_idents() {
     assert_eq!(TokenType::Ident, n.token_type);
 }
 
+#[test]
+fn test_into_vec() {
+    let source = "x := 1 + y";
+    let tokens = LexerIter::new(source).into_vec().unwrap();
+    assert_eq!(5, tokens.len());
+    assert_eq!("x", tokens[0].value);
+    assert_eq!(":=", tokens[1].value);
+    assert_eq!("1", tokens[2].value);
+    assert_eq!("+", tokens[3].value);
+    assert_eq!("y", tokens[4].value);
+    let tokens = LexerIter::new("").into_vec().unwrap();
+    assert_eq!(true, tokens.is_empty());
+}
+
 #[test]
 fn test_iter_chars() {
     let crab = '🦀';
@@ -230,26 +525,36 @@ fn test_iter_chars() {
     assert_eq!(true, lexer_iter.current.is_none());
 }
 
+#[test]
+fn test_lex_all() {
+    let tokens = lex_all("x := 1 // one").unwrap();
+    let values: Vec<&str> = tokens.iter().map(|t| t.value).collect();
+    assert_eq!(vec!["x", ":=", "1", "// one", "EOF"], values);
+    assert_eq!(EOF_TOKEN, lex_all("").unwrap()[0]);
+    let err = lex_all("x := 1 ^ 2").unwrap_err();
+    assert_eq!(UNRECOGNIZED_TOKEN, err.message());
+    assert_eq!(7, err.byte_offset());
+}
+
+#[test]
+fn test_lex_skip_comments() {
+    let tokens = lex_skip_comments(FACTORIAL).unwrap();
+    assert_eq!(
+        0,
+        tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Comment)
+            .count()
+    );
+    assert_eq!(lex_all(FACTORIAL).unwrap().len() - 2, tokens.len());
+    assert_eq!(EOF_TOKEN, tokens[tokens.len() - 1]);
+    let err = lex_skip_comments("// ok\n/* not closed").unwrap_err();
+    assert_eq!(COMMENT_IS_MISSING_CLOSING_SEQUENCE, err.message());
+}
+
 #[test]
 fn test_lexing() {
-    let source = r#"
-        /*
-         * The classic factorial function in a
-         * continuation-passing style.
-         */
-        actor Factorial() in
-            func fact(x) in
-                // Use continuation-passing style
-                func fact_cps(n, k) in
-                    if n < 2 then k
-                    else fact_cps(n - 1, n * k) end
-                end
-                fact_cps(x, 1)
-            end
-            handle ask x in
-                fact(x)
-            end
-        end"#;
+    let source = FACTORIAL;
     let mut lexer_iter = LexerIter::new(source);
     let comment = r#"/*
          * The classic factorial function in a
@@ -396,13 +701,12 @@ fn test_lexing() {
     let n = lexer_iter.next().unwrap();
     assert_eq!("end", n.value);
     assert_eq!(TokenType::Keyword, n.token_type);
-    // `handle` and `ask` will be recognized later as a "weak" keywords
     let n = lexer_iter.next().unwrap();
     assert_eq!("handle", n.value);
-    assert_eq!(TokenType::Ident, n.token_type);
+    assert_eq!(TokenType::WeakKeyword, n.token_type);
     let n = lexer_iter.next().unwrap();
     assert_eq!("ask", n.value);
-    assert_eq!(TokenType::Ident, n.token_type);
+    assert_eq!(TokenType::WeakKeyword, n.token_type);
     let n = lexer_iter.next().unwrap();
     assert_eq!("x", n.value);
     assert_eq!(TokenType::Ident, n.token_type);
@@ -432,6 +736,104 @@ fn test_lexing() {
     assert_eq!(TokenType::Eof, n.token_type);
 }
 
+#[test]
+fn test_lexer_error_display() {
+    let source = "x := 1\n/* open";
+    let err = lex_all(source).unwrap_err();
+    assert_eq!(7, err.byte_index());
+    assert_eq!(COMMENT_IS_MISSING_CLOSING_SEQUENCE, err.message());
+    assert_eq!(
+        "Lexer error at byte 7: Comment is missing closing sequence '*/'",
+        err.to_string()
+    );
+    // Usable as a boxed error with `?`
+    fn lex(source: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        Ok(lex_all(source)?.len())
+    }
+    assert_eq!(2, lex("x").unwrap());
+    assert_eq!(
+        "Lexer error at byte 0: Unrecognized token",
+        lex("^").unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn test_lexer_error_into_diagnostic() {
+    let source = "x = 1\ny = 23.0x";
+    let tokens = LexerIter::new(source).into_vec();
+    let err = tokens.unwrap_err();
+    assert_eq!(FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF, err.message());
+    let d = err.into_diagnostic(Some("main.torq"));
+    assert_eq!(DiagnosticKind::Error, d.kind);
+    assert_eq!(FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF, d.message);
+    assert_eq!(
+        Span {
+            start_byte: 10,
+            end_byte: 11,
+            line: 2,
+            column: 5
+        },
+        d.span
+    );
+    assert_eq!(false, d.hint.as_deref().unwrap_or("").is_empty());
+    assert_eq!(Some("main.torq"), d.source_name.as_deref());
+    let rendered = render_diagnostic(&d, source);
+    assert!(rendered.to_lowercase().contains("floating point suffix"));
+    assert!(rendered.contains("main.torq:2:5"));
+    assert!(rendered.contains("2 | y = 23.0x\n"));
+}
+
+#[test]
+fn test_lexer_error_recovery_hint() {
+    let sources = [
+        "/* x", "1.5x", "`x", "1q", "$''", "0x", "'x", "\"x", "<<T", "^",
+    ];
+    for source in sources {
+        let err = LexerIter::new(source).into_vec().unwrap_err();
+        assert_eq!(false, err.recovery_hint().is_empty());
+        assert_eq!(None, err.into_diagnostic(None).source_name);
+    }
+}
+
+#[test]
+fn test_lexer_error_with_source_context() {
+    let source = "a\nb\n'\u{1F980}' ^bar baz\nc";
+    let err = lex_all(source).unwrap_err();
+    assert_eq!(
+        "error at 3:5: Unrecognized token\n  | '\u{1F980}' ^bar baz\n  |     ^",
+        err.with_source_context(source)
+    );
+    // An index past the end of the source is placed at its end
+    assert_eq!(
+        "error at 1:2: Unrecognized token\n  | x\n  |  ^",
+        err.with_source_context("x")
+    );
+    assert_eq!(
+        "error at 1:1: Unrecognized token\n  | \n  | ^",
+        err.with_source_context("")
+    );
+}
+
+#[test]
+fn test_lexer_error_position() {
+    let source = "a\n  b\n'🦀' ^";
+    let mut lexer_iter = LexerIter::new(source);
+    assert_eq!("a", lexer_iter.next().unwrap().value);
+    assert_eq!("b", lexer_iter.next().unwrap().value);
+    assert_eq!("'🦀'", lexer_iter.next().unwrap().value);
+    let err = lexer_iter.next().err().unwrap();
+    assert_eq!(UNRECOGNIZED_TOKEN, err.message);
+    assert_eq!(13, err.byte_offset());
+    assert_eq!(3, err.line());
+    // The crab is one char wide even though it is four bytes long
+    assert_eq!(5, err.column());
+    let source = "x\n\n  23.0x";
+    let err = LexerIter::new(source).into_vec().err().unwrap();
+    assert_eq!(5, err.byte_offset());
+    assert_eq!(3, err.line());
+    assert_eq!(3, err.column());
+}
+
 #[test]
 fn test_line_comment() {
     let source = "//";
@@ -467,6 +869,44 @@ fn test_line_comment() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_iterator() {
+    let tokens = LexerIter::new(FACTORIAL)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(58, tokens.len());
+    assert_eq!(EOF_TOKEN, tokens[57]);
+    assert_eq!(
+        1,
+        tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Eof)
+            .count()
+    );
+    let idents = LexerIter::new("a 1 b")
+        .filter_map(Result::ok)
+        .filter(|t| t.token_type == TokenType::Ident)
+        .map(|t| t.value)
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["a", "b"], idents);
+    let mut lexer_iter = LexerIter::new("");
+    assert_eq!(Some(Ok(EOF_TOKEN)), Iterator::next(&mut lexer_iter));
+    assert_eq!(None, Iterator::next(&mut lexer_iter));
+    assert_eq!(None, Iterator::next(&mut lexer_iter));
+}
+
+#[test]
+fn test_iterator_after_error() {
+    let mut lexer_iter = LexerIter::new("a 'b c");
+    assert_eq!("a", Iterator::next(&mut lexer_iter).unwrap().unwrap().value);
+    let err = Iterator::next(&mut lexer_iter).unwrap().unwrap_err();
+    assert_eq!(STR_IS_MISSING_CLOSING_SINGLE_QUOTE, err.message());
+    assert_eq!(Some(Ok(EOF_TOKEN)), Iterator::next(&mut lexer_iter));
+    assert_eq!(None, Iterator::next(&mut lexer_iter));
+    let r = LexerIter::new("x ^ y").collect::<Result<Vec<_>, _>>();
+    assert_eq!(UNRECOGNIZED_TOKEN, r.unwrap_err().message());
+}
+
 #[test]
 fn test_keywords() {
     let source = "act actor begin break case catch continue do else elseif end eof false finally for func if import in local null of proc return self skip spawn then throw true try var when while";
@@ -586,6 +1026,31 @@ fn test_multi_digit_int() {
     assert_eq!("23", n.value);
     assert_eq!(0, n.byte_index);
     assert_eq!(TokenType::Int, n.token_type);
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+}
+
+#[test]
+fn test_octal_int() {
+    for source in ["0o0", "0o777", "0O17", "0o17l", "0o7L"] {
+        let mut lexer_iter = LexerIter::new(source);
+        let n = lexer_iter.next().unwrap();
+        assert_eq!(source, n.value);
+        assert_eq!(0, n.byte_index);
+        assert_eq!(TokenType::Int, n.token_type);
+        assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    }
+    let tokens = lex_all("(0o17)").unwrap();
+    assert_eq!("0o17", tokens[1].value);
+    assert_eq!(")", tokens[2].value);
+}
+
+#[test]
+fn test_octal_int_errors() {
+    for source in ["0o", "0o8", "0o78", "0o19", "0O ", "0o;"] {
+        let err = LexerIter::new(source).next().unwrap_err();
+        assert_eq!(INVALID_OCTAL_NUMBER, err.message());
+        assert_eq!(0, err.byte_offset());
+    }
 }
 
 #[test]
@@ -618,6 +1083,45 @@ fn test_one_char_symbols() {
     assert_parse_eq("~");
 }
 
+#[test]
+fn test_peek() {
+    let mut lexer_iter = LexerIter::new("a + 1");
+    assert_eq!("a", lexer_iter.peek().unwrap().value);
+    // Peeking again returns the same token
+    assert_eq!("a", lexer_iter.peek().unwrap().value);
+    assert_eq!("a", lexer_iter.next().unwrap().value);
+    assert_eq!("+", lexer_iter.peek().unwrap().value);
+    assert_eq!("+", lexer_iter.next().unwrap().value);
+    assert_eq!("1", lexer_iter.next().unwrap().value);
+    assert_eq!(&EOF_TOKEN, lexer_iter.peek().unwrap());
+    assert_eq!(&EOF_TOKEN, lexer_iter.peek().unwrap());
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    assert_eq!(&EOF_TOKEN, lexer_iter.peek().unwrap());
+}
+
+#[test]
+fn test_peek_error() {
+    let mut lexer_iter = LexerIter::new("a 12a");
+    assert_eq!("a", lexer_iter.next().unwrap().value);
+    let first = lexer_iter.peek().unwrap_err() as *const LexerError;
+    let second = lexer_iter.peek().unwrap_err() as *const LexerError;
+    assert_eq!(first, second);
+    assert_eq!(
+        INTEGER_SUFFIX_MUST_BE_ONE_OF,
+        lexer_iter.peek().unwrap_err().message()
+    );
+    let err = lexer_iter.next().unwrap_err();
+    assert_eq!(INTEGER_SUFFIX_MUST_BE_ONE_OF, err.message());
+}
+
+#[test]
+fn test_peek_with_iterator() {
+    let mut lexer_iter = LexerIter::new("x y");
+    assert_eq!("x", lexer_iter.peek().unwrap().value);
+    let values: Vec<&str> = lexer_iter.map(|r| r.unwrap().value).collect();
+    assert_eq!(vec!["x", "y", "EOF"], values);
+}
+
 #[test]
 fn test_quoted_ident() {
     // Empty identifier (will be rejected by parser)
@@ -652,6 +1156,7 @@ fn test_real() {
     assert_eq!("23.1", n.value);
     assert_eq!(0, n.byte_index);
     assert_eq!(TokenType::Flt, n.token_type);
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
 #[test]
@@ -694,6 +1199,7 @@ fn test_real_with_one_digit_exponent() {
     assert_eq!("1.0e-1", n.value);
     assert_eq!(0, n.byte_index);
     assert_eq!(TokenType::Flt, n.token_type);
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
 #[test]
@@ -714,10 +1220,12 @@ fn test_real_with_invalid_suffix_error() {
     if r.is_ok() {
         panic!("Error expected");
     }
-    assert_eq!(
-        FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF,
-        r.err().unwrap().message
-    );
+    let err = r.err().unwrap();
+    assert_eq!(FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF, err.message);
+    // Number errors are reported at the first digit of the number
+    assert_eq!(0, err.byte_offset());
+    assert_eq!(1, err.line());
+    assert_eq!(1, err.column());
 }
 
 #[test]
@@ -750,6 +1258,25 @@ fn test_single_digit_int() {
     assert_eq!("1", n.value);
     assert_eq!(0, n.byte_index);
     assert_eq!(TokenType::Int, n.token_type);
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+}
+
+#[test]
+fn test_source_remaining() {
+    let source = "hello world";
+    let mut lexer_iter = LexerIter::new(source);
+    assert_eq!(0, lexer_iter.bytes_consumed());
+    assert_eq!(source, lexer_iter.source_remaining());
+    assert_eq!("hello", lexer_iter.next().unwrap().value);
+    assert_eq!(5, lexer_iter.bytes_consumed());
+    assert_eq!(" world", lexer_iter.source_remaining());
+    // Reading the position does not advance the lexer
+    assert_eq!(5, lexer_iter.bytes_consumed());
+    assert_eq!("world", lexer_iter.next().unwrap().value);
+    assert_eq!(11, lexer_iter.bytes_consumed());
+    assert_eq!("", lexer_iter.source_remaining());
+    assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    assert_eq!(11, lexer_iter.bytes_consumed());
 }
 
 #[test]
@@ -790,6 +1317,148 @@ fn test_single_quoted_str() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_str_unicode_escape() {
+    for source in [
+        r#""\u0041""#,
+        r"'\u00e9'",
+        r#""\U0001F600""#,
+        r"'a\uABCDb'",
+        r#""\\u""#,
+    ] {
+        let mut lexer_iter = LexerIter::new(source);
+        let n = lexer_iter.next().unwrap();
+        assert_eq!(source, n.value);
+        assert_eq!(TokenType::Str, n.token_type);
+        assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    }
+}
+
+#[test]
+fn test_str_unicode_escape_errors() {
+    for source in [
+        r#""\u004""#,
+        r"'\u00G1'",
+        r#""\U0001F60""#,
+        r"'\U'",
+        r#""\u"#,
+    ] {
+        let err = LexerIter::new(source).next().unwrap_err();
+        assert_eq!(INVALID_UNICODE_ESCAPE, err.message());
+        assert_eq!(1, err.byte_offset());
+    }
+}
+
+#[test]
+fn test_token_at_byte() {
+    let source = "hello world";
+    let token = token_at_byte(source, 0).unwrap();
+    assert_eq!("hello", token.value);
+    assert_eq!(TokenType::Ident, token.token_type);
+    assert_eq!("hello", token_at_byte(source, 4).unwrap().value);
+    // Whitespace between tokens answers the next token
+    assert_eq!("world", token_at_byte(source, 5).unwrap().value);
+    assert_eq!("world", token_at_byte(source, 10).unwrap().value);
+    assert_eq!(EOF_TOKEN, token_at_byte(source, 11).unwrap());
+    assert_eq!(EOF_TOKEN, token_at_byte(source, 100).unwrap());
+    // The crab spans bytes 5 to 8 inside the string token at byte 4
+    let source = "x = '🦀' + 1";
+    let token = token_at_byte(source, 6).unwrap();
+    assert_eq!("'🦀'", token.value);
+    assert_eq!(4, token.byte_index);
+    assert_eq!("1", token_at_byte(source, 13).unwrap().value);
+    assert_eq!(EOF_TOKEN, token_at_byte(source, 14).unwrap());
+    // Errors before the requested byte are reported
+    let err = token_at_byte("'abc", 2).unwrap_err();
+    assert_eq!(STR_IS_MISSING_CLOSING_SINGLE_QUOTE, err.message());
+}
+
+#[test]
+fn test_token_stream() {
+    let mut stream = TokenStream::new(lex_all("f(x)").unwrap());
+    assert_eq!("f", stream.peek().value);
+    assert_eq!("(", stream.peek_n(1).value);
+    assert_eq!(EOF_TOKEN, *stream.peek_n(4));
+    assert_eq!(EOF_TOKEN, *stream.peek_n(usize::MAX));
+    assert_eq!("f", stream.advance().value);
+    let saved = stream.save();
+    assert_eq!("(", stream.expect(TokenType::OneCharSym).unwrap().value);
+    assert_eq!("x", stream.expect(TokenType::Ident).unwrap().value);
+    stream.restore(saved);
+    assert_eq!("(", stream.peek().value);
+    stream.advance();
+    stream.advance();
+    assert_eq!(")", stream.advance().value);
+    assert_eq!(true, stream.is_at_end());
+    // Advancing at the end keeps answering EOF
+    assert_eq!(EOF_TOKEN, *stream.advance());
+    assert_eq!(EOF_TOKEN, *stream.advance());
+    assert_eq!(EOF_TOKEN, *stream.peek());
+}
+
+#[test]
+fn test_token_stream_appends_eof() {
+    let mut stream = TokenStream::new(LexerIter::new("a").into_vec().unwrap());
+    assert_eq!(false, stream.is_at_end());
+    assert_eq!("a", stream.advance().value);
+    assert_eq!(EOF_TOKEN, *stream.peek());
+    let stream = TokenStream::new(vec![]);
+    assert_eq!(true, stream.is_at_end());
+    assert_eq!(EOF_TOKEN, *stream.peek());
+}
+
+#[test]
+fn test_token_stream_expect_error() {
+    let mut stream = TokenStream::new(lex_all("x := 1").unwrap());
+    let err = stream.expect(TokenType::Keyword).unwrap_err();
+    assert_eq!(
+        ParseError {
+            expected: TokenType::Keyword,
+            found: TokenType::Ident,
+            byte_index: 0
+        },
+        err
+    );
+    assert_eq!(
+        "Expected Keyword but found Ident at byte 0",
+        err.to_string()
+    );
+    // A failed expectation does not move the cursor
+    assert_eq!("x", stream.peek().value);
+}
+
+#[test]
+fn test_tokenize_into() {
+    let mut buf = Vec::with_capacity(16);
+    let count = tokenize_into("a + b", &mut buf).unwrap();
+    assert_eq!(3, count);
+    assert_eq!(3, buf.len());
+    assert_eq!("a", buf[0].value);
+    assert_eq!("+", buf[1].value);
+    assert_eq!("b", buf[2].value);
+    let capacity = buf.capacity();
+    // Reusing the buffer replaces the previous tokens without reallocating
+    let count = tokenize_into("if x then y end", &mut buf).unwrap();
+    assert_eq!(5, count);
+    assert_eq!("if", buf[0].value);
+    assert_eq!(TokenType::Keyword, buf[0].token_type);
+    assert_eq!("end", buf[4].value);
+    assert_eq!(capacity, buf.capacity());
+}
+
+#[test]
+fn test_tokenize_into_error_clears_buf() {
+    let mut buf = Vec::new();
+    tokenize_into("a b c", &mut buf).unwrap();
+    assert_eq!(3, buf.len());
+    let r = tokenize_into("a b 23.0x", &mut buf);
+    assert_eq!(
+        FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF,
+        r.err().unwrap().message
+    );
+    assert_eq!(true, buf.is_empty());
+}
+
 #[test]
 fn test_three_char_symbols() {
     assert_parse_eq("...");
@@ -804,6 +1473,121 @@ fn test_three_equals() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_tokenizer_errors_end_iteration() {
+    let mut tokenizer = Tokenizer::new("a 23.0x b");
+    assert_eq!("a", tokenizer.next().unwrap().unwrap().value);
+    let err = tokenizer.next().unwrap().err().unwrap();
+    assert_eq!(FLOATING_POINT_SUFFIX_MUST_BE_ONE_OF, err.message);
+    assert_eq!(None, tokenizer.next());
+}
+
+#[test]
+fn test_tokenizer_for_parser() {
+    let raw = LexerIter::new(FACTORIAL).into_vec().unwrap();
+    let tokens = Tokenizer::for_parser(FACTORIAL)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        0,
+        tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Comment)
+            .count()
+    );
+    let raw_without_comments: Vec<_> = raw
+        .into_iter()
+        .filter(|t| t.token_type != TokenType::Comment)
+        .collect();
+    assert_eq!(raw_without_comments, tokens);
+}
+
+#[test]
+fn test_tokenizer_modes() {
+    let tokens = Tokenizer::new(FACTORIAL)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(LexerIter::new(FACTORIAL).into_vec().unwrap(), tokens);
+    let tokens = Tokenizer::for_syntax_highlight(FACTORIAL)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        2,
+        tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Comment)
+            .count()
+    );
+    let keywords: Vec<_> =
+        Tokenizer::with_mode("if x then y // c\n end", TokenizerMode::ONLY_KEYWORDS)
+            .map(|t| t.unwrap().value)
+            .collect();
+    assert_eq!(vec!["if", "then", "end"], keywords);
+    let keywords: Vec<_> =
+        Tokenizer::with_mode("handle ask x in y end", TokenizerMode::ONLY_KEYWORDS)
+            .map(|t| t.unwrap().value)
+            .collect();
+    assert_eq!(vec!["handle", "ask", "in", "end"], keywords);
+    let mode = TokenizerMode::SKIP_COMMENTS | TokenizerMode::ONLY_KEYWORDS;
+    assert!(mode.contains(TokenizerMode::SKIP_COMMENTS));
+    assert!(!mode.contains(TokenizerMode::SKIP_WHITESPACE_TOKENS));
+    assert_eq!(mode, Tokenizer::with_mode("", mode).mode());
+}
+
+#[test]
+fn test_to_display_string() {
+    let mut lexer_iter = LexerIter::new("act 23 1.5f 3.14m // note\n== ... + foo");
+    for expected in [
+        "act", "23", "1.5f", "3.14m", "// note", "==", "...", "+", "foo",
+    ] {
+        let n = lexer_iter.next().unwrap();
+        assert_eq!(expected, n.to_display_string(false));
+        assert_eq!(expected, n.to_display_string(true));
+    }
+    assert_eq!("", lexer_iter.next().unwrap().to_display_string(false));
+}
+
+#[test]
+fn test_to_display_string_quoted_ident() {
+    // Valid plain identifier is unquoted unless forced
+    let n = LexerIter::new("`foo_1`").next().unwrap();
+    assert_eq!("foo_1", n.to_display_string(false));
+    assert_eq!("`foo_1`", n.to_display_string(true));
+    // Keyword stays quoted because unquoting would change its meaning
+    let n = LexerIter::new("`end`").next().unwrap();
+    assert_eq!("`end`", n.to_display_string(false));
+    // Weak keywords stay quoted too, so that the display string lexes back to an identifier
+    for source in ["`ask`", "`tell`", "`as`", "`handle`"] {
+        let n = LexerIter::new(source).next().unwrap();
+        assert_eq!(TokenType::Ident, n.token_type);
+        let display = n.to_display_string(false);
+        assert_eq!(source, display);
+        let relexed = LexerIter::new(&display).next().unwrap();
+        assert_eq!(TokenType::Ident, relexed.token_type);
+    }
+    // Content that is not a plain identifier stays quoted
+    let n = LexerIter::new("`this is an ident`").next().unwrap();
+    assert_eq!("`this is an ident`", n.to_display_string(false));
+    let n = LexerIter::new("`1abc`").next().unwrap();
+    assert_eq!("`1abc`", n.to_display_string(false));
+    let n = LexerIter::new("``").next().unwrap();
+    assert_eq!("``", n.to_display_string(false));
+}
+
+#[test]
+fn test_to_display_string_str() {
+    let n = LexerIter::new(r#""already double""#).next().unwrap();
+    assert_eq!(r#""already double""#, n.to_display_string(false));
+    let n = LexerIter::new("'simple string'").next().unwrap();
+    assert_eq!(r#""simple string""#, n.to_display_string(false));
+    let n = LexerIter::new("''").next().unwrap();
+    assert_eq!(r#""""#, n.to_display_string(false));
+    let n = LexerIter::new(r#"'it\'s "quoted"'"#).next().unwrap();
+    assert_eq!(r#""it's \"quoted\"""#, n.to_display_string(false));
+    let n = LexerIter::new(r#"'a\\b\nc'"#).next().unwrap();
+    assert_eq!(r#""a\\b\nc""#, n.to_display_string(false));
+}
+
 #[test]
 fn test_two_char_symbols() {
     // There are 11 two char symbols:
@@ -855,6 +1639,21 @@ fn test_four_periods() {
     assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
 }
 
+#[test]
+fn test_weak_keywords() {
+    for source in ["as", "ask", "handle", "tell"] {
+        let mut lexer_iter = LexerIter::new(source);
+        let n = lexer_iter.next().unwrap();
+        assert_eq!(source, n.value);
+        assert_eq!(TokenType::WeakKeyword, n.token_type);
+        assert_eq!(EOF_TOKEN, lexer_iter.next().unwrap());
+    }
+    for source in ["a", "asks", "tells", "handler", "`ask`"] {
+        let n = LexerIter::new(source).next().unwrap();
+        assert_eq!(TokenType::Ident, n.token_type);
+    }
+}
+
 #[test]
 fn test_five_periods() {
     let source = ".....";
@@ -0,0 +1,191 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+//! Macro expander performs simple textual substitution on source before lexing. A macro is used
+//! by writing its name prefixed with a dollar sign, such as `$MAX_SIZE`, and is replaced by its
+//! definition. Definitions may use other macros.
+//!
+//! Substitution is purely textual: macros are also expanded inside strings and comments, and a
+//! dollar sign not followed by an identifier char is left alone. Byte offsets in the expanded
+//! source do not correspond to byte offsets in the original source, so lexer errors reported
+//! against expanded source cannot be mapped back to the original.
+
+use std::collections::HashMap;
+use std::fmt;
+
+// A macro that uses another macro counts as one more level
+const MAX_EXPANSION_DEPTH: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum MacroExpansionError {
+    Circular(String),
+    RecursionLimit,
+    Undefined(String),
+}
+
+impl fmt::Display for MacroExpansionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroExpansionError::Circular(name) => {
+                write!(f, "Macro ${} is defined in terms of itself", name)
+            }
+            MacroExpansionError::RecursionLimit => write!(
+                f,
+                "Macro expansion exceeded the maximum depth of {}",
+                MAX_EXPANSION_DEPTH
+            ),
+            MacroExpansionError::Undefined(name) => write!(f, "Macro ${} is not defined", name),
+        }
+    }
+}
+
+impl std::error::Error for MacroExpansionError {}
+
+#[derive(Debug, Default)]
+pub struct MacroExpander {
+    definitions: HashMap<String, String>,
+}
+
+impl MacroExpander {
+    pub fn new() -> Self {
+        MacroExpander {
+            definitions: HashMap::new(),
+        }
+    }
+
+    /// Define (or redefine) the macro `name` without its dollar sign prefix.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.definitions.insert(name.to_string(), value.to_string());
+    }
+
+    /// Return `source` with every macro use replaced by its fully expanded definition.
+    pub fn expand(&self, source: &str) -> Result<String, MacroExpansionError> {
+        let mut expanded = String::with_capacity(source.len());
+        let mut active = Vec::new();
+        self.expand_into(source, &mut expanded, &mut active)?;
+        Ok(expanded)
+    }
+
+    /*
+     * `active` holds the names of the macros currently being expanded, outermost first.
+     */
+    fn expand_into<'a>(
+        &'a self,
+        text: &str,
+        expanded: &mut String,
+        active: &mut Vec<&'a str>,
+    ) -> Result<(), MacroExpansionError> {
+        let mut rest = text;
+        while let Some(dollar) = rest.find('$') {
+            expanded.push_str(&rest[..dollar]);
+            let after = &rest[dollar + 1..];
+            let name_len = Self::macro_name_len(after);
+            if name_len == 0 {
+                expanded.push('$');
+                rest = after;
+                continue;
+            }
+            let name = &after[..name_len];
+            let (name, value) = self
+                .definitions
+                .get_key_value(name)
+                .ok_or_else(|| MacroExpansionError::Undefined(name.to_string()))?;
+            if active.contains(&name.as_str()) {
+                return Err(MacroExpansionError::Circular(name.clone()));
+            }
+            if active.len() == MAX_EXPANSION_DEPTH {
+                return Err(MacroExpansionError::RecursionLimit);
+            }
+            active.push(name);
+            self.expand_into(value, expanded, active)?;
+            active.pop();
+            rest = &after[name_len..];
+        }
+        expanded.push_str(rest);
+        Ok(())
+    }
+
+    /*
+     * Return the byte length of the macro name at the start of `text`, or 0 if there is none. A
+     * name begins with a letter or underscore followed by letters, digits, or underscores.
+     */
+    fn macro_name_len(text: &str) -> usize {
+        let mut chars = text.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+            _ => return 0,
+        }
+        1 + chars
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_circular() {
+        let mut expander = MacroExpander::new();
+        expander.define("A", "1 + $B");
+        expander.define("B", "2 + $A");
+        assert_eq!(
+            Err(MacroExpansionError::Circular("A".to_string())),
+            expander.expand("x := $A")
+        );
+        expander.define("SELF", "$SELF");
+        assert_eq!(
+            Err(MacroExpansionError::Circular("SELF".to_string())),
+            expander.expand("$SELF")
+        );
+    }
+
+    #[test]
+    fn test_expand_recursion_limit() {
+        let mut expander = MacroExpander::new();
+        for i in 0..MAX_EXPANSION_DEPTH {
+            expander.define(&format!("M{}", i), &format!("$M{}", i + 1));
+        }
+        expander.define(&format!("M{}", MAX_EXPANSION_DEPTH), "done");
+        assert_eq!(
+            Err(MacroExpansionError::RecursionLimit),
+            expander.expand("$M0")
+        );
+        assert_eq!(Ok("done".to_string()), expander.expand("$M1"));
+    }
+
+    #[test]
+    fn test_expand_two_levels() {
+        let mut expander = MacroExpander::new();
+        expander.define("WIDTH", "80");
+        expander.define("AREA", "$WIDTH * $WIDTH");
+        assert_eq!(
+            Ok("var a = 80 * 80 + 1".to_string()),
+            expander.expand("var a = $AREA + 1")
+        );
+    }
+
+    #[test]
+    fn test_expand_without_macros() {
+        let expander = MacroExpander::new();
+        assert_eq!(Ok("".to_string()), expander.expand(""));
+        assert_eq!(Ok("1 + 2".to_string()), expander.expand("1 + 2"));
+        // A dollar sign without a macro name is left as is
+        assert_eq!(Ok("$ $1 $'c'".to_string()), expander.expand("$ $1 $'c'"));
+    }
+
+    #[test]
+    fn test_expand_undefined() {
+        let mut expander = MacroExpander::new();
+        expander.define("A", "$MISSING");
+        assert_eq!(
+            Err(MacroExpansionError::Undefined("MISSING".to_string())),
+            expander.expand("1 + $A")
+        );
+    }
+}
@@ -5,4 +5,7 @@
  * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
  */
 
+pub mod diagnostic;
 pub mod lexer;
+pub mod macro_expander;
+pub mod token_value;
@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) 2024 Torqware LLC. All rights reserved.
+ *
+ * You should have received a copy of the Torq Lang License v1.0 along with this program.
+ * If not, see http://torq-lang.github.io/licensing/torq-lang-license-v1_0.
+ */
+
+//! The lexer returns slices of the source and never allocates. Functions here decode those
+//! slices into owned values when a later phase needs them.
+
+use crate::lang::lexer::{Token, TokenType};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnescapeError {
+    InvalidCodePoint(u32),
+    InvalidEscape(char),
+    InvalidUnicodeEscape,
+    NotAStr,
+}
+
+impl fmt::Display for UnescapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnescapeError::InvalidCodePoint(c) => write!(f, "Invalid code point U+{:X}", c),
+            UnescapeError::InvalidEscape(c) => write!(f, "Invalid escape sequence \\{}", c),
+            UnescapeError::InvalidUnicodeEscape => {
+                write!(
+                    f,
+                    "Unicode escape must be \\u with 4 hex digits or \\U with 8"
+                )
+            }
+            UnescapeError::NotAStr => write!(f, "Token is not a string"),
+        }
+    }
+}
+
+impl std::error::Error for UnescapeError {}
+
+/// Decode the content of a `Str` token. Quotes are removed and the escapes `\\`, `\'`, `\"`,
+/// `\n`, `\r`, `\t`, `\uXXXX` and `\UXXXXXXXX` are replaced by the chars they denote. A heredoc
+/// body is returned verbatim.
+pub fn unescape(token: &Token) -> Result<String, UnescapeError> {
+    if token.token_type != TokenType::Str {
+        return Err(UnescapeError::NotAStr);
+    }
+    if let Some(body) = token.heredoc_body() {
+        return Ok(body.to_string());
+    }
+    let content = &token.value[1..token.value.len() - 1];
+    let mut answer = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            answer.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => decode_unicode_escape(&mut chars, 4)?,
+            Some('U') => decode_unicode_escape(&mut chars, 8)?,
+            Some(other) => return Err(UnescapeError::InvalidEscape(other)),
+            None => return Err(UnescapeError::InvalidEscape('\\')),
+        };
+        answer.push(escaped);
+    }
+    Ok(answer)
+}
+
+/*
+ * Pre-condition:
+ *   `chars` is positioned after the "u" or "U" of a unicode escape.
+ *
+ * Post-condition:
+ *   `chars` is positioned after the last hex digit of the escape.
+ */
+fn decode_unicode_escape(
+    chars: &mut std::str::Chars,
+    digit_count: usize,
+) -> Result<char, UnescapeError> {
+    let mut code_point = 0u32;
+    for _ in 0..digit_count {
+        let digit = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or(UnescapeError::InvalidUnicodeEscape)?;
+        code_point = code_point * 16 + digit;
+    }
+    char::from_u32(code_point).ok_or(UnescapeError::InvalidCodePoint(code_point))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn str_token(value: &str) -> Token<'_> {
+        Token {
+            value,
+            byte_index: 0,
+            token_type: TokenType::Str,
+        }
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(Ok("A".to_string()), unescape(&str_token(r#""\u0041""#)));
+        assert_eq!(
+            Ok("\u{1F600}".to_string()),
+            unescape(&str_token(r"'\U0001F600'"))
+        );
+        assert_eq!(
+            Ok("a\tb\n'\"\\".to_string()),
+            unescape(&str_token(r#""a\tb\n\'\"\\""#))
+        );
+        assert_eq!(
+            Ok("line".to_string()),
+            unescape(&str_token("<<EOT\nline\nEOT"))
+        );
+    }
+
+    #[test]
+    fn test_unescape_errors() {
+        assert_eq!(
+            Err(UnescapeError::InvalidCodePoint(0xD800)),
+            unescape(&str_token(r"'\uD800'"))
+        );
+        assert_eq!(
+            Err(UnescapeError::InvalidEscape('q')),
+            unescape(&str_token(r"'\q'"))
+        );
+        assert_eq!(
+            Err(UnescapeError::InvalidUnicodeEscape),
+            unescape(&str_token(r"'\u00'"))
+        );
+        let token = Token {
+            value: "abc",
+            byte_index: 0,
+            token_type: TokenType::Ident,
+        };
+        assert_eq!(Err(UnescapeError::NotAStr), unescape(&token));
+    }
+}
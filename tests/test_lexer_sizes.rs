@@ -1,4 +1,6 @@
-use torq_lang::lang::lexer::{Token, TokenType};
+use std::mem::offset_of;
+
+use torq_lang::lang::lexer::{LexerError, Token, TokenType};
 
 #[test]
 fn show_namaste_sizes() {
@@ -20,13 +22,32 @@ fn show_three_crabs_sizes() {
 
 #[test]
 fn show_token_size() {
-    // 24 bytes
+    // 24 bytes -- 16 (value) + 4 (byte_index) + 1 (token_type) + 3 (padding to 8 byte alignment)
     let v = "";
     let token = Token {
         value: v,
         byte_index: 0,
-        token_type: TokenType::Unknown,
+        token_type: TokenType::Ident,
     };
     println!("Token size: {:?}", size_of_val(&token));
     assert_eq!(24, size_of_val(&token));
+    // The token type shares the second 8 byte word with the byte index
+    assert_eq!(0, offset_of!(Token, value));
+    assert_eq!(16, offset_of!(Token, byte_index));
+    assert_eq!(20, offset_of!(Token, token_type));
+}
+
+#[test]
+fn show_token_type_size() {
+    // 1 byte
+    println!("TokenType size: {:?}", size_of::<TokenType>());
+    assert_eq!(1, size_of::<TokenType>());
+}
+
+#[test]
+fn show_lexer_error_size() {
+    // 40 bytes -- a 16 byte message slice plus a 20 byte index (char, char index, byte index, line,
+    // and column) padded to a multiple of 8
+    println!("LexerError size: {:?}", size_of::<LexerError>());
+    assert_eq!(40, size_of::<LexerError>());
 }
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use torq_lang::klvm::value::{Comp, Scalar, ScalarOrComp, ToBeDefined};
+use torq_lang::klvm::actor::ActorRef;
+use torq_lang::klvm::env::Env;
+use torq_lang::klvm::value::{Closure, Decimal, List, Obj, Rec, Scalar, Tuple, Value};
 
 #[test]
 fn show_rust_sizes() {
@@ -39,26 +42,69 @@ fn show_rust_sizes() {
 
 #[test]
 fn show_torq_sizes() {
-    // 16 bytes
+    // 32 bytes -- the largest payload is the 24 byte `Decimal` of `Scalar::Dec`
     let v = Scalar::Bool(true);
     println!("Scalar::Bool(true) size: {}", size_of_val(&v));
-    assert_eq!(16, size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
+    // 24 bytes -- a 16 byte `i128` mantissa and a 1 byte scale, packed to 8-byte alignment
+    let v = Decimal::new(314, 2);
+    println!("Decimal size: {}", size_of_val(&v));
+    assert_eq!(24, size_of_val(&v));
+    // 32 bytes
+    let v = Scalar::Dec(Decimal::new(314, 2));
+    println!(
+        "Scalar::Dec(Decimal::new(314, 2)) size: {}",
+        size_of_val(&v)
+    );
+    assert_eq!(32, size_of_val(&v));
+    // 32 bytes
+    let v = Scalar::Null;
+    println!("Scalar::Null size: {}", size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
+    // 32 bytes
+    let v = Scalar::Str("Hello".into());
+    println!("Scalar::Str(\"Hello\") size: {}", size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
+    // 32 bytes -- the largest payload is the 24 byte `Decimal` of `Value::Dec`. `Obj` holds a `Rec`
+    // for state and a `Vec` for the method table, so `Value::Obj` boxes it.
+    let v = Value::Bool(true);
+    println!("Value::Bool(true) size: {}", size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
     // 32 bytes
-    let v = Comp::Rec(ToBeDefined::new());
-    println!("Comp::Rec(ToBeDefined::new()) size: {}", size_of_val(&v));
+    let v = Value::Dec(Decimal::new(314, 2));
+    println!("Value::Dec(Decimal::new(314, 2)) size: {}", size_of_val(&v));
     assert_eq!(32, size_of_val(&v));
     // 32 bytes
-    let v = ScalarOrComp::Scalar(Scalar::Bool(false));
+    let v = Value::Closure(Arc::new(Closure::new("f", 0, 0, vec![], Env::new())));
     println!(
-        "ScalarOrComp::Scalar(Scalar::Bool(false)) size: {}",
+        "Value::Closure(Arc::new(Closure::new(..))) size: {}",
         size_of_val(&v)
     );
     assert_eq!(32, size_of_val(&v));
     // 32 bytes
-    let v = ScalarOrComp::Comp(Comp::Rec(ToBeDefined::new()));
+    let v = Value::ActorRef(ActorRef::new(1));
     println!(
-        "ScalarOrComp::Comp(Comp::Rec(ToBeDefined::new())) size: {}",
+        "Value::ActorRef(ActorRef::new(1)) size: {}",
         size_of_val(&v)
     );
     assert_eq!(32, size_of_val(&v));
+    // 32 bytes
+    let v = Value::List(List::new());
+    println!("Value::List(List::new()) size: {}", size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
+    // 32 bytes
+    let v = Value::Obj(Box::new(Obj::new(Rec::new())));
+    println!(
+        "Value::Obj(Box::new(Obj::new(Rec::new()))) size: {}",
+        size_of_val(&v)
+    );
+    assert_eq!(32, size_of_val(&v));
+    // 32 bytes
+    let v = Value::Rec(Rec::new());
+    println!("Value::Rec(Rec::new()) size: {}", size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
+    // 32 bytes
+    let v = Value::Tuple(Tuple::new(vec![]));
+    println!("Value::Tuple(Tuple::new(vec![])) size: {}", size_of_val(&v));
+    assert_eq!(32, size_of_val(&v));
 }